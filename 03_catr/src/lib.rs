@@ -1,37 +1,273 @@
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-use clap::{App, Arg};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use std::{cmp, mem};
+use bzip2::bufread::MultiBzDecoder;
+use clap::{App, Arg, ArgMatches};
+use encoding_rs::{
+    Decoder, Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8,
+    WINDOWS_1252,
+};
+use flate2::bufread::MultiGzDecoder;
+use regex::bytes::Regex;
+use xz2::bufread::XzDecoder;
 
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
     number: bool,
     number_nonblank: bool,
+    number_regex: Option<Regex>,
+    line_numbers: LineNumbers,
+    no_renumber: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
+    squeeze_blank: bool,
+    decompress: bool,
+    reverse: Option<Vec<u8>>,
+    from_encoding: Option<InputEncoding>,
+    to_encoding: Option<&'static Encoding>,
+}
+
+#[derive(Debug, PartialEq)]
+enum InputEncoding {
+    Auto,
+    Label(&'static Encoding),
+}
+
+// How line numbers are rendered, modeled on nl(1)
+#[derive(Debug)]
+struct LineNumbers {
+    start: i64,
+    increment: i64,
+    width: usize,
+    separator: String,
+    format: NumberFormat,
+}
+
+#[derive(Debug, PartialEq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    // longest magic number we need to look at
+    const MAGIC_LEN: usize = 6;
+    // the magic numbers, with a block size digit standing in for bzip2's
+    const MAGIC: [&'static [u8]; 4] = [
+        &[0x1f, 0x8b],
+        b"BZh1",
+        &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+        &[0x28, 0xb5, 0x2f, 0xfd],
+    ];
+
+    fn detect(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.len() >= 4
+            && magic.starts_with(b"BZh")
+            && (b'1'..=b'9').contains(&magic[3])
+        {
+            // the digit is the block size, which plain text rarely has
+            Some(Compression::Bzip2)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    // Whether more bytes could still turn out to be a magic number
+    fn undecided(magic: &[u8]) -> bool {
+        Compression::detect(magic).is_none()
+            && Compression::MAGIC
+                .iter()
+                .any(|m| m.len() > magic.len() && m.starts_with(magic))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum NumberFormat {
+    Left,
+    Right,
+    RightZero,
+}
+
+// State that may carry over from one file to the next
+struct LineState {
+    number: i64,
+    last_blank: bool,
+    // false when the previous file ended without a newline, so that its
+    // last line carries on into the next file
+    at_line_start: bool,
 }
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
 pub fn run(config: Config) -> MyResult<()> {
-    for filename in config.files {
-        let mut i = 1;
-        match open(&filename) {
+    // stdout is line buffered, so each line shows up as soon as it is read
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match config.to_encoding {
+        None => cat_all(&config, &mut out)?,
+        Some(encoding) => {
+            let mut encoded = EncodeWriter::new(&mut out, encoding);
+            cat_all(&config, &mut encoded)?;
+            encoded.finish()?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn cat_all(config: &Config, out: &mut impl Write) -> MyResult<()> {
+    let mut state = LineState {
+        number: config.line_numbers.start,
+        last_blank: false,
+        at_line_start: true,
+    };
+    for filename in &config.files {
+        match open(filename, config) {
             Err(err) => eprintln!("Failed to open {}: {}", filename, err),
-            Ok(fh) => for line in fh.lines() {
-                let l = line.unwrap();
-                if config.number || 
-                 (config.number_nonblank && l.len() > 0) {
-                    print!("{:6}\t", i);
-                    i += 1;
+            Ok(fh) => {
+                if !config.no_renumber {
+                    state.number = config.line_numbers.start;
+                    state.at_line_start = true;
+                }
+                // a corrupt input only loses the rest of that input, but
+                // there is no point going on once stdout is gone
+                if let Err(err) = cat_file(config, fh, out, &mut state) {
+                    match err.downcast_ref::<io::Error>() {
+                        Some(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                            return Err(err)
+                        }
+                        _ => eprintln!("{}: {}", filename, err),
+                    }
                 }
-                println!("{}", l);
-
             }
         };
     }
     Ok(())
 }
 
+// Copy one input to the output. Lines are handled as raw bytes so that
+// invalid UTF-8 and CRLF terminators come through untouched.
+fn cat_file(
+    config: &Config,
+    mut fh: Box<dyn BufRead>,
+    out: &mut impl Write,
+    state: &mut LineState,
+) -> MyResult<()> {
+    if !config.number
+        && !config.number_nonblank
+        && config.number_regex.is_none()
+        && !config.show_ends
+        && !config.show_tabs
+        && !config.show_nonprinting
+        && !config.squeeze_blank
+    {
+        io::copy(&mut fh, out)?;
+        return Ok(());
+    }
+
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if fh.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let continued = !state.at_line_start;
+        state.at_line_start = line.ends_with(b"\n");
+        let blank = !continued && line == b"\n";
+        if config.squeeze_blank && blank && state.last_blank {
+            continue;
+        }
+        state.last_blank = blank;
+        let numbered = !continued
+            && (config.number
+                || (config.number_nonblank && !blank)
+                || config.number_regex.as_ref().is_some_and(|re| {
+                    re.is_match(line.strip_suffix(b"\n").unwrap_or(&line))
+                }));
+        if numbered {
+            write_number(&config.line_numbers, state.number, out)?;
+            state.number += config.line_numbers.increment;
+        }
+        write_line(config, &line, out)?;
+    }
+    Ok(())
+}
+
+fn write_number(fmt: &LineNumbers, num: i64, out: &mut impl Write) -> io::Result<()> {
+    let width = fmt.width;
+    match fmt.format {
+        NumberFormat::Left => write!(out, "{:<width$}", num, width = width)?,
+        NumberFormat::Right => write!(out, "{:>width$}", num, width = width)?,
+        NumberFormat::RightZero => write!(out, "{:0width$}", num, width = width)?,
+    }
+    out.write_all(fmt.separator.as_bytes())
+}
+
+// Write a line, applying the -E, -T and -v transformations
+fn write_line(config: &Config, line: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let (mut body, newline) = match line.strip_suffix(b"\n") {
+        Some(body) => (body, true),
+        None => (line, false),
+    };
+    // like GNU cat, -E marks a CRLF ending as "^M$"
+    let mut carriage_return = false;
+    if config.show_ends && newline {
+        if let Some(stripped) = body.strip_suffix(b"\r") {
+            body = stripped;
+            carriage_return = true;
+        }
+    }
+    if !config.show_tabs && !config.show_nonprinting {
+        out.write_all(body)?;
+    } else {
+        for &b in body {
+            write_byte(config, b, out)?;
+        }
+    }
+    if newline {
+        if carriage_return {
+            out.write_all(b"^M")?;
+        }
+        if config.show_ends {
+            out.write_all(b"$")?;
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+// Render a single byte, using ^ and M- notation for -v
+fn write_byte(config: &Config, b: u8, out: &mut impl Write) -> io::Result<()> {
+    if b == b'\t' {
+        return out.write_all(if config.show_tabs { b"^I" } else { b"\t" });
+    }
+    if !config.show_nonprinting {
+        return out.write_all(&[b]);
+    }
+    let mut c = b;
+    if c >= 128 {
+        out.write_all(b"M-")?;
+        c -= 128;
+    }
+    match c {
+        0..=31 => out.write_all(&[b'^', c + 64]),
+        127 => out.write_all(b"^?"),
+        _ => out.write_all(&[c]),
+    }
+}
+
 macro_rules! args_app {
     () => {{
     App::new("catr")
@@ -60,18 +296,234 @@ macro_rules! args_app {
         .takes_value(false)
         .conflicts_with("number"),
     )
+    .arg(
+        Arg::with_name("number_regex")
+        .value_name("REGEX")
+        .long("number-regex")
+        .help("number only lines that match REGEX")
+        .takes_value(true)
+        .conflicts_with_all(&["number", "number_nonblank"]),
+    )
+    .arg(
+        Arg::with_name("start")
+        .value_name("NUMBER")
+        .long("starting-line-number")
+        .help("first line number for each file; numbering options imply -n")
+        .default_value("1")
+        .allow_hyphen_values(true),
+    )
+    .arg(
+        Arg::with_name("increment")
+        .value_name("NUMBER")
+        .long("line-increment")
+        .help("line number increment at each line")
+        .default_value("1")
+        .allow_hyphen_values(true),
+    )
+    .arg(
+        Arg::with_name("width")
+        .value_name("NUMBER")
+        .long("number-width")
+        .help("use NUMBER columns for line numbers")
+        .default_value("6"),
+    )
+    .arg(
+        Arg::with_name("separator")
+        .value_name("STRING")
+        .long("number-separator")
+        .help("add STRING after (possible) line number")
+        .default_value("\t"),
+    )
+    .arg(
+        Arg::with_name("format")
+        .value_name("FORMAT")
+        .long("number-format")
+        .help("insert line numbers according to FORMAT: ln, rn or rz")
+        .default_value("rn")
+        .possible_values(&["ln", "rn", "rz"]),
+    )
+    .arg(
+        Arg::with_name("no_decompress")
+        .long("no-decompress")
+        .help("do not decompress gzip, bzip2, xz or zstd input")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("from_encoding")
+        .value_name("ENCODING")
+        .long("from-encoding")
+        .help("decode input from ENCODING, or guess with \"auto\"")
+        .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("to_encoding")
+        .value_name("ENCODING")
+        .long("to-encoding")
+        .help("encode output as ENCODING, default UTF-8")
+        .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("reverse")
+        .long("reverse")
+        .help("print records in reverse order, like tac")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("record_separator")
+        .value_name("STRING")
+        .long("separator")
+        .help("use STRING as the record separator for --reverse")
+        .takes_value(true)
+        .requires("reverse"),
+    )
+    .arg(
+        Arg::with_name("no_renumber")
+        .long("no-renumber")
+        .help("do not reset line numbers for each file")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("show_all")
+        .short("A")
+        .long("show-all")
+        .help("equivalent to -vET")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("e")
+        .short("e")
+        .help("equivalent to -vE")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("show_ends")
+        .short("E")
+        .long("show-ends")
+        .help("display $ at end of each line")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("squeeze_blank")
+        .short("s")
+        .long("squeeze-blank")
+        .help("suppress repeated empty output lines")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("t")
+        .short("t")
+        .help("equivalent to -vT")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("show_tabs")
+        .short("T")
+        .long("show-tabs")
+        .help("display TAB characters as ^I")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("show_nonprinting")
+        .short("v")
+        .long("show-nonprinting")
+        .help("use ^ and M- notation, except for LFD and TAB")
+        .takes_value(false),
+    )
     }};
 }
 
 pub fn get_args() -> MyResult<Config> {
     let matches = args_app!().get_matches();
+    let show_all = matches.is_present("show_all");
+    let e = matches.is_present("e");
+    let t = matches.is_present("t");
+
+    let number_regex = matches
+        .value_of("number_regex")
+        .map(|re| {
+            Regex::new(re).map_err(|_| format!("Invalid --number-regex \"{}\"", re))
+        })
+        .transpose()?;
+
+    let reverse = if matches.is_present("reverse") {
+        let separator = matches.value_of("record_separator").unwrap_or("\n");
+        if separator.is_empty() {
+            return Err(From::from("separator cannot be empty"));
+        }
+        Some(separator.as_bytes().to_vec())
+    } else {
+        None
+    };
+
+    let from_encoding = matches
+        .value_of("from_encoding")
+        .map(|label| match label {
+            "auto" => Ok(InputEncoding::Auto),
+            _ => parse_encoding(label).map(InputEncoding::Label),
+        })
+        .transpose()?;
+
+    let to_encoding = matches
+        .value_of("to_encoding")
+        .map(parse_encoding)
+        .transpose()?
+        .filter(|&encoding| encoding != UTF_8);
+
+    let width = parse_value(&matches, "width", "line number field width")?;
+    if width == 0 {
+        return Err(From::from("invalid line number field width -- 0"));
+    }
+
+    let line_numbers = LineNumbers {
+        start: parse_value(&matches, "start", "starting line number")?,
+        increment: parse_value(&matches, "increment", "line number increment")?,
+        width,
+        separator: matches.value_of("separator").unwrap().to_string(),
+        format: match matches.value_of("format").unwrap() {
+            "ln" => NumberFormat::Left,
+            "rz" => NumberFormat::RightZero,
+            _ => NumberFormat::Right,
+        },
+    };
+
+    // asking for a numbering style without a numbering mode means -n
+    let numbering = ["start", "increment", "width", "separator", "format", "no_renumber"]
+        .iter()
+        .any(|arg| matches.occurrences_of(arg) > 0);
+    let number_nonblank = matches.is_present("number_nonblank");
+    let number = matches.is_present("number")
+        || (numbering && !number_nonblank && number_regex.is_none());
+
     Ok(Config {
         files: matches.values_of_lossy("files").unwrap(),
-        number: matches.is_present("number"),
-        number_nonblank: matches.is_present("number_nonblank"),
+        number,
+        number_nonblank,
+        number_regex,
+        line_numbers,
+        no_renumber: matches.is_present("no_renumber"),
+        show_ends: show_all || e || matches.is_present("show_ends"),
+        show_tabs: show_all || t || matches.is_present("show_tabs"),
+        show_nonprinting: show_all || e || t
+            || matches.is_present("show_nonprinting"),
+        squeeze_blank: matches.is_present("squeeze_blank"),
+        decompress: !matches.is_present("no_decompress"),
+        reverse,
+        from_encoding,
+        to_encoding,
     })
 }
 
+fn parse_encoding(label: &str) -> MyResult<&'static Encoding> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| From::from(format!("unknown encoding -- {}", label)))
+}
+
+fn parse_value<T: FromStr>(matches: &ArgMatches, name: &str, what: &str) -> MyResult<T> {
+    let val = matches.value_of(name).unwrap();
+    val.parse()
+        .map_err(|_| From::from(format!("invalid {} -- {}", what, val)))
+}
+
 #[test]
 fn check_args() {
     let mut values: Vec<&str>;
@@ -96,11 +548,515 @@ fn check_args() {
     assert_eq!(values, ["foo", "bar"]);
     assert!(m2.is_present("number"));
 
+    // display flags
+    let m3 = args_app!().get_matches_from(vec![
+        "catr".to_string(),
+        "-sAT".to_string(),
+        "foo".to_string(),
+    ]);
+
+    assert!(m3.is_present("squeeze_blank"));
+    assert!(m3.is_present("show_all"));
+    assert!(m3.is_present("show_tabs"));
+    assert!(!m3.is_present("show_nonprinting"));
+
+    // nl-style numbering
+    let m4 = args_app!().get_matches_from(vec![
+        "catr".to_string(),
+        "--starting-line-number".to_string(),
+        "-5".to_string(),
+        "--number-format".to_string(),
+        "rz".to_string(),
+    ]);
+
+    assert_eq!(m4.value_of("start"), Some("-5"));
+    assert_eq!(m4.value_of("increment"), Some("1"));
+    assert_eq!(m4.value_of("width"), Some("6"));
+    assert_eq!(m4.value_of("format"), Some("rz"));
+    assert!(!m4.is_present("no_renumber"));
+
+}
+
+fn open(filename: &str, config: &Config) -> MyResult<Box<dyn BufRead>> {
+    let file = match filename {
+        "-" => None,
+        _ => Some(File::open(filename)?),
+    };
+    // keep a second handle on regular files so --reverse can seek
+    let seekable = match (&file, &config.reverse) {
+        (Some(file), Some(_)) if file.metadata()?.is_file() => {
+            Some(file.try_clone()?)
+        }
+        _ => None,
+    };
+    let fh: Box<dyn BufRead> = match file {
+        None => Box::new(BufReader::new(io::stdin())),
+        Some(file) => Box::new(BufReader::new(file)),
+    };
+    let (mut fh, compression) = if config.decompress {
+        decompressed(fh)?
+    } else {
+        (fh, None)
+    };
+    if let Some(from_encoding) = &config.from_encoding {
+        fh = Box::new(BufReader::new(DecodeReader::new(fh, from_encoding)?));
+    }
+
+    let separator = match &config.reverse {
+        None => return Ok(fh),
+        Some(separator) => separator,
+    };
+    let reversed: Box<dyn Read> = match seekable {
+        Some(file) if compression.is_none() && config.from_encoding.is_none() => {
+            Box::new(ReverseReader::new(file, separator)?)
+        }
+        // pipes and decoded streams can't seek, so buffer them
+        _ => {
+            let mut buf = Vec::new();
+            fh.read_to_end(&mut buf)?;
+            Box::new(ReverseReader::new(Cursor::new(buf), separator)?)
+        }
+    };
+    Ok(Box::new(BufReader::new(reversed)))
+}
+
+// Streams any input encoding out as UTF-8
+struct DecodeReader<R> {
+    inner: R,
+    decoder: Decoder,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    done: bool,
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+impl<R: BufRead> DecodeReader<R> {
+    fn new(mut inner: R, encoding: &InputEncoding) -> io::Result<Self> {
+        let encoding = match encoding {
+            InputEncoding::Label(encoding) => encoding,
+            InputEncoding::Auto => guess_encoding(inner.fill_buf()?),
+        };
+        Ok(DecodeReader {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            buf: Vec::new(),
+            buf_pos: 0,
+            done: false,
+        })
+    }
+}
+
+impl<R: BufRead> Read for DecodeReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.buf_pos == self.buf.len() && !self.done {
+            let src = self.inner.fill_buf()?;
+            let last = src.is_empty();
+            let max_len = self
+                .decoder
+                .max_utf8_buffer_length(src.len())
+                .ok_or_else(|| io::Error::other("input too large"))?;
+            self.buf.resize(max_len, 0);
+            let (_, read, written, _) =
+                self.decoder.decode_to_utf8(src, &mut self.buf, last);
+            self.inner.consume(read);
+            self.buf.truncate(written);
+            self.buf_pos = 0;
+            self.done = last;
+        }
+        let remaining = &self.buf[self.buf_pos..];
+        let n = cmp::min(out.len(), remaining.len());
+        out[..n].copy_from_slice(&remaining[..n]);
+        self.buf_pos += n;
+        Ok(n)
+    }
+}
+
+// Pick an encoding from a byte order mark, falling back to a guess based
+// on the first block: NUL-heavy UTF-16, valid UTF-8, or else Latin-1.
+fn guess_encoding(head: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return encoding;
+    }
+    // ASCII text in UTF-16 has a NUL in every other byte
+    let nuls_at = |parity| {
+        head.iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let pairs = head.len() / 2;
+    if pairs > 0 && nuls_at(1) * 2 > pairs {
+        return UTF_16LE;
+    }
+    if pairs > 0 && nuls_at(0) * 2 > pairs {
+        return UTF_16BE;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => UTF_8,
+        // a multibyte sequence cut off at the end of the block is fine
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => WINDOWS_1252,
+    }
+}
+
+#[test]
+fn check_guess_encoding() {
+    assert_eq!(guess_encoding(b""), UTF_8);
+    assert_eq!(guess_encoding(b"plain ascii"), UTF_8);
+    assert_eq!(guess_encoding("café".as_bytes()), UTF_8);
+    assert_eq!(guess_encoding(b"caf\xc3"), UTF_8);
+    assert_eq!(guess_encoding(b"\xef\xbb\xbfbom"), UTF_8);
+    assert_eq!(guess_encoding(b"\xff\xfeh\x00i\x00"), UTF_16LE);
+    assert_eq!(guess_encoding(b"\xfe\xff\x00h\x00i"), UTF_16BE);
+    assert_eq!(guess_encoding(b"h\x00i\x00\xe9\x00"), UTF_16LE);
+    assert_eq!(guess_encoding(b"\x00h\x00i\x00\xe9"), UTF_16BE);
+    assert_eq!(guess_encoding(b"caf\xe9 cr\xe8me"), WINDOWS_1252);
+}
+
+// Re-encodes the UTF-8 written to it. Bytes that are not valid UTF-8
+// become U+FFFD and characters the target can't represent become '?'.
+struct EncodeWriter<W> {
+    inner: W,
+    encoding: &'static Encoding,
+    encoder: Encoder,
+    // an incomplete UTF-8 sequence left over from the last write
+    pending: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl<W: Write> EncodeWriter<W> {
+    fn new(inner: W, encoding: &'static Encoding) -> Self {
+        EncodeWriter {
+            inner,
+            encoding,
+            encoder: encoding.new_encoder(),
+            pending: Vec::new(),
+            out: Vec::new(),
+        }
     }
+
+    fn encode(&mut self, text: &str, last: bool) -> io::Result<()> {
+        // encoding_rs never encodes to UTF-16, so do that by hand
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            for unit in text.encode_utf16() {
+                let bytes = if self.encoding == UTF_16LE {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                };
+                self.inner.write_all(&bytes)?;
+            }
+            return Ok(());
+        }
+
+        let mut src = text;
+        loop {
+            let max_len = self
+                .encoder
+                .max_buffer_length_from_utf8_without_replacement(src.len())
+                .unwrap_or(src.len() * 4 + 16);
+            self.out.resize(max_len, 0);
+            let (result, read, written) = self
+                .encoder
+                .encode_from_utf8_without_replacement(src, &mut self.out, last);
+            self.inner.write_all(&self.out[..written])?;
+            src = &src[read..];
+            match result {
+                EncoderResult::InputEmpty => return Ok(()),
+                EncoderResult::OutputFull => {}
+                EncoderResult::Unmappable(_) => self.inner.write_all(b"?")?,
+            }
+        }
+    }
+
+    // Write out whatever is still buffered, which must happen once all
+    // input has been written.
+    fn finish(&mut self) -> io::Result<()> {
+        let pending = mem::take(&mut self.pending);
+        let text = String::from_utf8_lossy(&pending).into_owned();
+        self.encode(&text, true)?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for EncodeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let mut start = 0;
+        loop {
+            match std::str::from_utf8(&self.pending[start..]) {
+                Ok(text) => {
+                    let text = text.to_string();
+                    self.encode(&text, false)?;
+                    start = self.pending.len();
+                    break;
+                }
+                Err(e) => {
+                    let valid = start + e.valid_up_to();
+                    let text = std::str::from_utf8(&self.pending[start..valid])
+                        .unwrap()
+                        .to_string();
+                    self.encode(&text, false)?;
+                    match e.error_len() {
+                        // wait for the rest of the sequence
+                        None => {
+                            start = valid;
+                            break;
+                        }
+                        Some(len) => {
+                            self.encode("\u{FFFD}", false)?;
+                            start = valid + len;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending.drain(..start);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn check_encode_writer() {
+    let encode = |chunks: &[&[u8]], label: &str| {
+        let mut out = Vec::new();
+        let mut writer =
+            EncodeWriter::new(&mut out, Encoding::for_label(label.as_bytes()).unwrap());
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        out
+    };
+
+    assert_eq!(encode(&[b"caf\xc3", b"\xa9\n"], "latin1"), b"caf\xe9\n");
+    assert_eq!(encode(&["€ ✓".as_bytes()], "latin1"), b"\x80 ?");
+    assert_eq!(encode(&[b"h\xffi"], "utf-16le"), b"h\x00\xfd\xffi\x00");
+    assert_eq!(encode(&["é".as_bytes()], "utf-16be"), b"\x00\xe9");
+    assert_eq!(encode(&[b"cut \xc3"], "latin1"), b"cut ?");
+}
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+// Yields the separator-terminated records of a seekable input in
+// reverse order. The input is read backwards in blocks, so only the
+// record currently being emitted has to fit in memory.
+struct ReverseReader<R> {
+    inner: R,
+    separator: Vec<u8>,
+    block_size: usize,
+    // offset of the first byte of `inner` that has been read
+    pos: u64,
+    // bytes read from `inner` but not yet emitted are buf[start..]; new
+    // blocks are read in just before them, so each byte is copied once
+    // (apart from when buf has to grow)
+    buf: Vec<u8>,
+    start: usize,
+    record: Vec<u8>,
+    record_pos: usize,
+}
+
+impl<R: Read + Seek> ReverseReader<R> {
+    fn new(inner: R, separator: &[u8]) -> io::Result<Self> {
+        ReverseReader::with_block_size(inner, separator, BLOCK_SIZE)
+    }
+
+    fn with_block_size(
+        mut inner: R,
+        separator: &[u8],
+        block_size: usize,
+    ) -> io::Result<Self> {
+        let pos = inner.seek(SeekFrom::End(0))?;
+        Ok(ReverseReader {
+            inner,
+            separator: separator.to_vec(),
+            block_size,
+            pos,
+            buf: Vec::new(),
+            start: 0,
+            record: Vec::new(),
+            record_pos: 0,
+        })
+    }
+
+    // Move the last record in `pending` into `record`, reading more of
+    // the input as needed. Returns false once everything is emitted.
+    fn next_record(&mut self) -> io::Result<bool> {
+        let sep_len = self.separator.len();
+        // a separator at the very end terminates the record we want,
+        // so only look for one that ends before that
+        let mut limit = (self.buf.len() - self.start).saturating_sub(1);
+        loop {
+            let pending = &self.buf[self.start..];
+            let found = rfind(&pending[..limit], &self.separator).map(|i| i + sep_len);
+            if found.is_some() || self.pos == 0 {
+                if pending.is_empty() {
+                    return Ok(false);
+                }
+                let record_start = self.start + found.unwrap_or(0);
+                self.record.clear();
+                self.record.extend_from_slice(&self.buf[record_start..]);
+                self.buf.truncate(record_start);
+                self.record_pos = 0;
+                return Ok(true);
+            }
+            let start = self.pos.saturating_sub(self.block_size as u64);
+            let len = (self.pos - start) as usize;
+            self.reserve_front(len);
+            self.start -= len;
+            self.inner.seek(SeekFrom::Start(start))?;
+            self.inner.read_exact(&mut self.buf[self.start..self.start + len])?;
+            self.pos = start;
+            // what was already searched can only complete a separator
+            // that starts in the new block
+            let end = self.buf.len() - self.start - 1;
+            limit = cmp::min(len + cmp::min(limit, sep_len - 1), end);
+        }
+    }
+
+    // Make room for len more bytes in front of the pending ones, at
+    // least doubling buf so that growing it stays linear overall
+    fn reserve_front(&mut self, len: usize) {
+        if self.start >= len {
+            return;
+        }
+        let pending = self.buf.len() - self.start;
+        let size = cmp::max(2 * self.buf.len(), pending + len);
+        let mut grown = vec![0; size];
+        grown[size - pending..].copy_from_slice(&self.buf[self.start..]);
+        self.start = size - pending;
+        self.buf = grown;
+    }
+}
+
+impl<R: Read + Seek> Read for ReverseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.record_pos == self.record.len() {
+            if !self.next_record()? {
+                return Ok(0);
+            }
+        }
+        let remaining = &self.record[self.record_pos..];
+        let n = cmp::min(buf.len(), remaining.len());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.record_pos += n;
+        Ok(n)
+    }
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[test]
+fn check_reverse_reader() {
+    let reverse = |input: &str, separator: &str, block_size: usize| {
+        let mut reader = ReverseReader::with_block_size(
+            Cursor::new(input.as_bytes().to_vec()),
+            separator.as_bytes(),
+            block_size,
+        )
+        .unwrap();
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        out
+    };
+
+    for block_size in [1, 2, 3, 7, BLOCK_SIZE] {
+        assert_eq!(reverse("", "\n", block_size), "");
+        assert_eq!(reverse("\n", "\n", block_size), "\n");
+        assert_eq!(reverse("a\nb\nc\n", "\n", block_size), "c\nb\na\n");
+        assert_eq!(reverse("a\n\nbb\n", "\n", block_size), "bb\n\na\n");
+        // like tac, a missing final separator is not added back
+        assert_eq!(reverse("a\nb", "\n", block_size), "ba\n");
+        assert_eq!(reverse("one--two--", "--", block_size), "two--one--");
+        assert_eq!(reverse("one--two", "--", block_size), "twoone--");
+        assert_eq!(reverse("no separator", "--", block_size), "no separator");
+    }
+
+    // records many blocks long
+    let long = "x".repeat(10_000);
+    let input = format!("{0}\n{0}y\n", long);
+    let expected = format!("{0}y\n{0}\n", long);
+    for block_size in [1, 3, 64] {
+        assert_eq!(reverse(&input, "\n", block_size), expected);
+        assert_eq!(reverse(&long, ",", block_size), long);
+    }
+}
+
+// Sniff the first bytes of a stream and, if they carry a known
+// compression signature, stream it through the matching decoder.
+// The sniffed bytes are put back in front so nothing is lost for
+// uncompressed input, even when reading from a pipe.
+fn decompressed(
+    mut fh: Box<dyn BufRead>,
+) -> MyResult<(Box<dyn BufRead>, Option<Compression>)> {
+    // look at what is already buffered, and only wait for more input
+    // when it might be the start of a magic number, so that short lines
+    // from a pipe or terminal are not held back
+    let peeked = fh.fill_buf()?;
+    let (fh, compression): (Box<dyn BufRead>, _) = if Compression::undecided(peeked) {
+        let mut magic = Vec::with_capacity(Compression::MAGIC_LEN);
+        while Compression::undecided(&magic) {
+            let buf = fh.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            magic.push(buf[0]);
+            fh.consume(1);
+        }
+        let compression = Compression::detect(&magic);
+        (Box::new(Cursor::new(magic).chain(fh)), compression)
+    } else {
+        let compression = Compression::detect(peeked);
+        (fh, compression)
+    };
+    let decoded: Box<dyn BufRead> = match compression {
+        None => Box::new(fh),
+        Some(Compression::Gzip) => {
+            Box::new(BufReader::new(MultiGzDecoder::new(fh)))
+        }
+        Some(Compression::Bzip2) => {
+            Box::new(BufReader::new(MultiBzDecoder::new(fh)))
+        }
+        Some(Compression::Xz) => {
+            Box::new(BufReader::new(XzDecoder::new_multi_decoder(fh)))
+        }
+        Some(Compression::Zstd) => {
+            Box::new(BufReader::new(zstd::Decoder::with_buffer(fh)?))
+        }
+    };
+    Ok((decoded, compression))
+}
+
+#[test]
+fn check_compression_magic() {
+    assert_eq!(Compression::detect(b"\x1f\x8b\x08\x00"), Some(Compression::Gzip));
+    assert_eq!(Compression::detect(b"BZh91AY"), Some(Compression::Bzip2));
+    assert_eq!(
+        Compression::detect(b"\xfd7zXZ\x00\x00"),
+        Some(Compression::Xz)
+    );
+    assert_eq!(
+        Compression::detect(b"\x28\xb5\x2f\xfd\x24"),
+        Some(Compression::Zstd)
+    );
+    assert_eq!(Compression::detect(b"BZ"), None);
+    assert_eq!(Compression::detect(b"BZh is text"), None);
+    assert_eq!(Compression::detect(b"BZh0"), None);
+    assert_eq!(Compression::detect(b"\xfd7zXZ"), None);
+    assert_eq!(Compression::detect(b"plain text"), None);
+    assert_eq!(Compression::detect(b""), None);
+
+    assert!(Compression::undecided(b""));
+    assert!(Compression::undecided(b"\x1f"));
+    assert!(Compression::undecided(b"BZh"));
+    assert!(Compression::undecided(b"\xfd7zX"));
+    assert!(!Compression::undecided(b"a\n"));
+    assert!(!Compression::undecided(b"BZhx"));
+    assert!(!Compression::undecided(b"\x1f\x8b"));
 }
\ No newline at end of file
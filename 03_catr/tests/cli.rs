@@ -11,6 +11,7 @@ const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const SPIDERS: &str = "tests/inputs/spiders.txt";
 const BUSTLE: &str = "tests/inputs/the-bustle.txt";
+const WHITESPACE: &str = "tests/inputs/whitespace.txt";
 
 // --------------------------------------------------
 #[test]
@@ -198,3 +199,317 @@ fn all_n() -> TestResult {
 fn all_b() -> TestResult {
     run(&[FOX, SPIDERS, BUSTLE, "-b"], "tests/expected/all.b.out")
 }
+
+// --------------------------------------------------
+fn run_bytes(args: &[&str], input: &[u8], expected: &[u8]) -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected.to_vec());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8_passthrough() -> TestResult {
+    let input = b"caf\xe9\r\n\xff\xfe\x00binary";
+    run_bytes(&["-"], input, input)
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8_n() -> TestResult {
+    run_bytes(
+        &["-n"],
+        b"caf\xe9\r\n\r\n\xff",
+        b"     1\tcaf\xe9\r\n     2\t\r\n     3\t\xff",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn crlf_b() -> TestResult {
+    run_bytes(
+        &["-b"],
+        b"one\r\n\ntwo\r\n",
+        b"     1\tone\r\n\n     2\ttwo\r\n",
+    )
+}
+
+// --------------------------------------------------
+fn run_raw(args: &[&str], expected_file: &str) -> TestResult {
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn whitespace_display_flags() -> TestResult {
+    for flag in &["A", "E", "T", "v", "s", "e", "t"] {
+        run_raw(
+            &[&format!("-{}", flag), WHITESPACE],
+            &format!("tests/expected/whitespace.txt.{}.out", flag),
+        )?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn whitespace_squeeze_n() -> TestResult {
+    run_raw(&["-sn", WHITESPACE], "tests/expected/whitespace.txt.sn.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn all_n_no_renumber() -> TestResult {
+    run(
+        &["-n", "--no-renumber", FOX, SPIDERS, BUSTLE],
+        "tests/expected/all.n.no-renumber.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_n_zero_padded() -> TestResult {
+    run(
+        &[
+            "-n",
+            "--starting-line-number",
+            "5",
+            "--line-increment",
+            "2",
+            "--number-width",
+            "3",
+            "--number-format",
+            "rz",
+            "--number-separator",
+            ": ",
+            BUSTLE,
+        ],
+        "tests/expected/the-bustle.txt.n.rz.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_number_regex() -> TestResult {
+    run(
+        &["--number-regex", "^The", "--number-format", "ln", BUSTLE],
+        "tests/expected/the-bustle.txt.regex.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_number_width() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--number-width", "0", BUSTLE])
+        .assert()
+        .failure()
+        .stderr("invalid line number field width -- 0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_compressed_n() -> TestResult {
+    for ext in &["gz", "bz2", "xz", "zst"] {
+        run(
+            &["-n", &format!("{}.{}", BUSTLE, ext)],
+            "tests/expected/the-bustle.txt.n.out",
+        )?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_compressed_stdin() -> TestResult {
+    let input = fs::read(format!("{}.gz", BUSTLE))?;
+    let expected = fs::read(BUSTLE)?;
+    run_bytes(&["-"], &input, &expected)
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_no_decompress() -> TestResult {
+    let input = fs::read(format!("{}.xz", BUSTLE))?;
+    run_bytes(&["--no-decompress"], &input, &input)
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_reverse() -> TestResult {
+    run(&["--reverse", BUSTLE], "tests/expected/the-bustle.txt.reverse.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_reverse_n_compressed() -> TestResult {
+    run(
+        &["--reverse", "-n", &format!("{}.zst", BUSTLE)],
+        "tests/expected/the-bustle.txt.reverse.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_stdin_reverse_separator() -> TestResult {
+    run_stdin(
+        BUSTLE,
+        &["--reverse", "--separator", ","],
+        "tests/expected/the-bustle.txt.reverse.comma.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_utf16_auto_n() -> TestResult {
+    run(
+        &["-n", "--from-encoding", "auto", "tests/inputs/the-bustle.utf16.txt"],
+        "tests/expected/the-bustle.txt.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_latin1() -> TestResult {
+    for label in &["auto", "latin1"] {
+        run(
+            &["--from-encoding", label, "tests/inputs/the-bustle.latin1.txt"],
+            "tests/expected/the-bustle.latin1.txt.out",
+        )?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_to_utf16() -> TestResult {
+    let utf16 = fs::read("tests/inputs/the-bustle.utf16.txt")?;
+    // the output has no byte order mark
+    run_bytes(
+        &["--to-encoding", "utf-16le"],
+        &fs::read(BUSTLE)?,
+        &utf16[2..],
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_encoding() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--from-encoding", "klingon", BUSTLE])
+        .assert()
+        .failure()
+        .stderr("unknown encoding -- klingon\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bzh_plain_text() -> TestResult {
+    let input = fs::read("tests/inputs/bzh.txt")?;
+    run_bytes(&["-"], &input, &input)
+}
+
+// --------------------------------------------------
+#[test]
+fn skips_corrupt_compressed() -> TestResult {
+    let expected = fs::read_to_string(FOX)?;
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs/corrupt.bz2", FOX])
+        .assert()
+        .success()
+        .stdout(expected)
+        .stderr(predicate::str::starts_with("tests/inputs/corrupt.bz2: "));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn numbering_options_imply_n() -> TestResult {
+    run(
+        &[
+            "--starting-line-number",
+            "5",
+            "--line-increment",
+            "2",
+            "--number-width",
+            "3",
+            "--number-format",
+            "rz",
+            "--number-separator",
+            ": ",
+            BUSTLE,
+        ],
+        "tests/expected/the-bustle.txt.n.rz.out",
+    )?;
+    run(
+        &["--no-renumber", FOX, SPIDERS, BUSTLE],
+        "tests/expected/all.n.no-renumber.out",
+    )?;
+    // numbering styles leave -b alone
+    run(&["-b", "--number-width", "6", BUSTLE], "tests/expected/the-bustle.txt.b.out")
+}
+
+// --------------------------------------------------
+// Write `first` to a running catr and return the first line it prints,
+// while its input is still open
+fn first_line_while_open(args: &[&str], first: &[u8]) -> Result<String, Box<dyn Error>> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::{Command as Process, Stdio};
+
+    let mut child = Process::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(first)?;
+    stdin.flush()?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = BufReader::new(stdout).read_line(&mut line);
+        let _ = tx.send(line);
+    });
+    let line = rx.recv_timeout(std::time::Duration::from_secs(5));
+    drop(stdin);
+    child.kill()?;
+    child.wait()?;
+    Ok(line?)
+}
+
+// --------------------------------------------------
+#[test]
+fn prints_lines_as_they_arrive() -> TestResult {
+    assert_eq!(first_line_while_open(&[], b"first line\n")?, "first line\n");
+    assert_eq!(
+        first_line_while_open(&["-n"], b"first line\n")?,
+        "     1\tfirst line\n"
+    );
+    // too short to rule out compression by length alone
+    assert_eq!(first_line_while_open(&["-n"], b"a\n")?, "     1\ta\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_renumber_continues_unterminated_line() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--no-renumber", "tests/inputs/no-eol.txt", FOX])
+        .assert()
+        .success()
+        .stdout("     1\ta\n     2\tbThe quick brown fox jumps over the lazy dog.\n");
+    Ok(())
+}
@@ -1,16 +1,55 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Write};
+use std::str;
 use clap::{App, Arg};
+use csv::{ReaderBuilder, WriterBuilder};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use regex::bytes::Regex;
+use unicode_segmentation::UnicodeSegmentation;
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
-    lines: usize,
-    bytes: Option<usize>,
+    lines: Count,
+    bytes: Option<Count>,
+    chars: Option<usize>,
+    graphemes: Option<usize>,
+    headers: bool,
+    header_format: String,
+    delimiter: u8,
+    pattern: Option<Pattern>,
+    csv_delimiter: Option<u8>,
+    keep_header: bool,
+    sample: Option<Sample>,
+}
+
+// Pick num random lines in one pass with reservoir sampling
+#[derive(Debug)]
+struct Sample {
+    num: usize,
+    seed: Option<u64>,
+    keep_order: bool,
+}
+
+// Stop at a line matching (--until) or not matching (--while) a regex
+#[derive(Debug)]
+struct Pattern {
+    regex: Regex,
+    until: bool,
+    inclusive: bool,
+    max_lines: Option<usize>,
+}
+
+// A line or byte count; a leading '-' means "all but the last NUM"
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Count {
+    First(usize),
+    AllButLast(usize),
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -30,46 +69,314 @@ pub fn get_args() -> MyResult<Config> {
         .value_name("LINES")
         .short("n")
         .long("lines")
-        .help("print the first NUM lines of each file, default 10")
+        .help("print the first NUM lines of each file, default 10; \
+               with a leading '-', all but the last NUM lines")
         .default_value("10")
-        .takes_value(true),
+        .takes_value(true)
+        .allow_hyphen_values(true),
     )
     .arg(
         Arg::with_name("bytes")
         .value_name("BYTES")
         .short("c")
         .long("bytes")
-        .help("print the first NUM bytes of each file")
+        .help("print the first NUM bytes of each file; \
+               with a leading '-', all but the last NUM bytes")
         .takes_value(true)
+        .allow_hyphen_values(true)
         .conflicts_with("lines"),
+    )
+    .arg(
+        Arg::with_name("chars")
+        .value_name("CHARS")
+        .long("chars")
+        .help("print the first NUM characters of each file")
+        .takes_value(true)
+        .conflicts_with_all(&["lines", "bytes"]),
+    )
+    .arg(
+        Arg::with_name("graphemes")
+        .value_name("GRAPHEMES")
+        .long("graphemes")
+        .help("print the first NUM grapheme clusters of each file")
+        .takes_value(true)
+        .conflicts_with_all(&["lines", "bytes", "chars"]),
+    )
+    .arg(
+        Arg::with_name("quiet")
+        .short("q")
+        .long("quiet")
+        .visible_alias("silent")
+        .help("never print headers giving file names")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("verbose")
+        .short("v")
+        .long("verbose")
+        .help("always print headers giving file names")
+        .takes_value(false)
+        .conflicts_with("quiet"),
+    )
+    .arg(
+        Arg::with_name("header_format")
+        .value_name("FORMAT")
+        .long("header-format")
+        .help("header line to print, with %f replaced by the file name")
+        .default_value("==> %f <==")
+        .takes_value(true)
+        .allow_hyphen_values(true),
+    )
+    .arg(
+        Arg::with_name("until")
+        .value_name("REGEX")
+        .long("until")
+        .help("print lines up to the first one matching REGEX")
+        .takes_value(true)
+        .conflicts_with_all(&["bytes", "chars", "graphemes"]),
+    )
+    .arg(
+        Arg::with_name("while")
+        .value_name("REGEX")
+        .long("while")
+        .help("print lines as long as they match REGEX")
+        .takes_value(true)
+        .conflicts_with_all(&["until", "bytes", "chars", "graphemes"]),
+    )
+    .arg(
+        Arg::with_name("inclusive")
+        .long("inclusive")
+        .help("with --until or --while, also print the line that stopped it")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("csv")
+        .long("csv")
+        .help("count CSV records instead of lines")
+        .takes_value(false)
+        .conflicts_with_all(&[
+            "bytes", "chars", "graphemes", "until", "while", "zero_terminated",
+        ]),
+    )
+    .arg(
+        Arg::with_name("tsv")
+        .long("tsv")
+        .help("count tab-separated records instead of lines")
+        .takes_value(false)
+        .conflicts_with_all(&[
+            "csv", "bytes", "chars", "graphemes", "until", "while", "zero_terminated",
+        ]),
+    )
+    .arg(
+        Arg::with_name("keep_header")
+        .long("keep-header")
+        .help("with --csv or --tsv, always print the header plus NUM records")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("sample")
+        .value_name("NUM")
+        .long("sample")
+        .help("print NUM lines chosen uniformly at random")
+        .takes_value(true)
+        .conflicts_with_all(&[
+            "lines", "bytes", "chars", "graphemes", "until", "while", "csv", "tsv",
+        ]),
+    )
+    .arg(
+        Arg::with_name("seed")
+        .value_name("SEED")
+        .long("seed")
+        .help("seed the random generator used by --sample")
+        .takes_value(true)
+        .requires("sample"),
+    )
+    .arg(
+        Arg::with_name("keep_order")
+        .long("keep-order")
+        .help("print sampled lines in their original order")
+        .takes_value(false)
+        .requires("sample"),
+    )
+    .arg(
+        Arg::with_name("zero_terminated")
+        .short("z")
+        .long("zero-terminated")
+        .help("line delimiter is NUL, not newline")
+        .takes_value(false),
     ).get_matches();
 
     let lines = matches
         .value_of("lines")
-        .map(parse_positive_int)
+        .map(parse_count)
         .transpose()
         .map_err(|e| format!("illegal line count -- {}", e))?;
 
     let bytes = matches
         .value_of("bytes")
-        .map(parse_positive_int)
+        .map(parse_count)
         .transpose()
         .map_err(|e| format!("illegal byte count -- {}", e))?;
 
+    let chars = matches
+        .value_of("chars")
+        .map(parse_positive_int)
+        .transpose()
+        .map_err(|e| format!("illegal character count -- {}", e))?;
+
+    let graphemes = matches
+        .value_of("graphemes")
+        .map(parse_positive_int)
+        .transpose()
+        .map_err(|e| format!("illegal grapheme count -- {}", e))?;
+
+    let until = matches.value_of("until");
+    let pattern = match until.or_else(|| matches.value_of("while")) {
+        None => None,
+        Some(re) => {
+            let regex = Regex::new(re)
+                .map_err(|_| format!("Invalid {} \"{}\"",
+                    if until.is_some() { "--until" } else { "--while" }, re))?;
+            // an explicit -n caps how many lines are printed
+            let max_lines = match lines {
+                Some(Count::First(num)) if matches.occurrences_of("lines") > 0 => Some(num),
+                Some(Count::AllButLast(num)) => {
+                    return Err(From::from(format!(
+                        "illegal line count -- -{}: must be positive with --until or --while",
+                        num
+                    )));
+                }
+                _ => None,
+            };
+            Some(Pattern {
+                regex,
+                until: until.is_some(),
+                inclusive: matches.is_present("inclusive"),
+                max_lines,
+            })
+        }
+    };
+
+    let sample = match matches.value_of("sample") {
+        None => None,
+        Some(num) => Some(Sample {
+            num: parse_positive_int(num)
+                .map_err(|e| format!("illegal sample count -- {}", e))?,
+            seed: matches
+                .value_of("seed")
+                .map(|s| s.parse().map_err(|_| format!("illegal seed -- {}", s)))
+                .transpose()?,
+            keep_order: matches.is_present("keep_order"),
+        }),
+    };
+
+    let files = matches.values_of_lossy("files").unwrap();
+    let headers = matches.is_present("verbose")
+        || (!matches.is_present("quiet") && files.len() > 1);
+
     Ok(Config {
-        files: matches.values_of_lossy("files").unwrap(),
+        files,
         lines: lines.unwrap(),
-        bytes
+        bytes,
+        chars,
+        graphemes,
+        headers,
+        header_format: matches.value_of("header_format").unwrap().to_string(),
+        delimiter: if matches.is_present("zero_terminated") { b'\0' } else { b'\n' },
+        pattern,
+        csv_delimiter: if matches.is_present("csv") {
+            Some(b',')
+        } else if matches.is_present("tsv") {
+            Some(b'\t')
+        } else {
+            None
+        },
+        keep_header: matches.is_present("keep_header"),
+        sample,
     })
 }
 
+// Parse a positive integer with an optional GNU-style multiplier suffix:
+// b (512), K/KiB (1024), KB (1000), M/MiB, MB and so on up to Y.
+// Error messages always start with the offending value.
 fn parse_positive_int(s: &str) -> MyResult<usize> {
-    match s.parse::<usize>() {
-        Ok(i) if i > 0 => Ok(i),
-        _ => Err(From::from(s)),
+    let body = s.strip_prefix('+').unwrap_or(s);
+    let split = body
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(split);
+    let multiplier = size_multiplier(suffix).ok_or(s)?;
+    // no digits at all, or zero
+    if digits.bytes().all(|b| b == b'0') {
+        return Err(From::from(s));
+    }
+    // with only digits left, parsing can fail only on overflow
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| multiplier.and_then(|m| i.checked_mul(m)))
+        .ok_or_else(|| From::from(format!("{}: value too large", s)))
+}
+
+// The multiplier for a size suffix: Some(None) if it overflows a usize
+fn size_multiplier(suffix: &str) -> Option<Option<usize>> {
+    let mut chars = suffix.chars();
+    let unit = match chars.next() {
+        None => return Some(Some(1)),
+        Some('b') if chars.as_str().is_empty() => return Some(Some(512)),
+        Some(c) => c,
+    };
+    let power = match unit {
+        'k' | 'K' => 1,
+        'm' | 'M' => 2,
+        'G' => 3,
+        'T' => 4,
+        'P' => 5,
+        'E' => 6,
+        'Z' => 7,
+        'Y' => 8,
+        _ => return None,
+    };
+    let base: usize = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return None,
+    };
+    Some(base.checked_pow(power))
+}
+
+fn parse_count(s: &str) -> MyResult<Count> {
+    match s.strip_prefix('-') {
+        Some(num) => parse_positive_int(num)
+            .map(Count::AllButLast)
+            .map_err(|e| From::from(format!("-{}", e))),
+        None => parse_positive_int(s).map(Count::First),
     }
 }
 
+#[test]
+fn test_parse_count() {
+    let res = parse_count("3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), Count::First(3));
+
+    let res = parse_count("-3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), Count::AllButLast(3));
+
+    // the whole value is reported on error
+    let res = parse_count("-foo");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "-foo".to_string());
+
+    let res = parse_count("-0");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "-0".to_string());
+
+    let res = parse_count("--3");
+    assert!(res.is_err());
+}
+
 #[test]
 fn test_parse_positive_int() {
     // 3 should work
@@ -86,45 +393,439 @@ fn test_parse_positive_int() {
     let res = parse_positive_int("0");
     assert!(res.is_err());
     assert_eq!(res.unwrap_err().to_string(), "0".to_string());
+
+    // multiplier suffixes
+    assert_eq!(parse_positive_int("2b").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1K").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1k").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1KiB").unwrap(), 1024);
+    assert_eq!(parse_positive_int("1KB").unwrap(), 1000);
+    assert_eq!(parse_positive_int("16MiB").unwrap(), 16777216);
+    assert_eq!(parse_positive_int("3MB").unwrap(), 3000000);
+    assert_eq!(parse_positive_int("1G").unwrap(), 1 << 30);
+    assert_eq!(parse_positive_int("1E").unwrap(), 1 << 60);
+
+    // bad suffixes and zero with a suffix should error
+    for bad in ["1X", "1bB", "1KiBB", "1iB", "K", "0K", "1 K"] {
+        let res = parse_positive_int(bad);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), bad.to_string());
+    }
+
+    // overflow should error
+    for big in ["16E", "1Z", "1Y", "99999999999999999999"] {
+        let res = parse_positive_int(big);
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            format!("{}: value too large", big)
+        );
+    }
 }
 
 pub fn run(config: Config) -> MyResult<()> {
-    let num_files = config.files.len();
     let mut file_num = 0;
+    let mut rng = match config.sample.as_ref().and_then(|s| s.seed) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
     for filename in &config.files {
         match open(filename) {
             Err(err) => eprintln!("Failed to open {}: {}", filename, err),
-            Ok(mut fh) => {
-                if num_files > 1 {
-                    println! ("{}==> {} <==",
+            Ok(fh) => {
+                if config.headers {
+                    writeln!(out, "{}{}",
                     if file_num > 0 { "\n" } else { "" },
-                    filename);
+                    config.header_format.replace("%f", filename))?;
                     file_num += 1;
                 }
-                match config.bytes {
-                    Some(num) => {
-                        let mut handle = fh.take(num as u64);
-                        let mut buf = vec![0; num ];
-                        let bytes_read = handle.read(&mut buf)?;
-                        print!("{}", 
-                            String::from_utf8_lossy(&buf[..bytes_read]));
-                    },
-                    None => {
-                        let mut line = String::new();
-                        for _i in 0..config.lines {
-                            let bytes = fh.read_line(&mut line)?;
-                            if bytes == 0 { break; }
-                            print!("{}", line);
-                            line.clear();
-                        }
-                    },
-                };
+                if let Some(num) = config.chars {
+                    head_chars(fh, num, &mut out)?;
+                } else if let Some(num) = config.graphemes {
+                    head_graphemes(fh, num, &mut out)?;
+                } else if let Some(sample) = &config.sample {
+                    head_sample(fh, sample, config.delimiter, &mut rng, &mut out)?;
+                } else if let Some(delimiter) = config.csv_delimiter {
+                    head_records(fh, delimiter, config.keep_header, config.lines, &mut out)?;
+                } else if let Some(pattern) = &config.pattern {
+                    head_pattern(fh, pattern, config.delimiter, &mut out)?;
+                } else {
+                    match config.bytes {
+                        Some(Count::First(num)) => {
+                            io::copy(&mut fh.take(num as u64), &mut out)?;
+                        },
+                        Some(Count::AllButLast(num)) => {
+                            head_bytes_but_last(fh, num, &mut out)?;
+                        },
+                        None => head_lines(fh, config.lines, config.delimiter, &mut out)?,
+                    };
+                }
+            }
+        };
+    }
+    Ok(())
+}
+
+fn head_lines(
+    mut fh: impl BufRead,
+    count: Count,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut line = Vec::new();
+    match count {
+        Count::First(num) => {
+            for _ in 0..num {
+                line.clear();
+                if fh.read_until(delimiter, &mut line)? == 0 { break; }
+                out.write_all(&line)?;
+            }
+        }
+        // hold back the last num lines; anything older can be printed
+        Count::AllButLast(num) => {
+            let mut held: VecDeque<Vec<u8>> = VecDeque::new();
+            loop {
+                let mut line = Vec::new();
+                if fh.read_until(delimiter, &mut line)? == 0 { break; }
+                if held.len() == num {
+                    out.write_all(&held.pop_front().unwrap())?;
+                }
+                held.push_back(line);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Algorithm R: the i-th line replaces a random slot with probability num/i
+fn head_sample(
+    mut fh: impl BufRead,
+    sample: &Sample,
+    delimiter: u8,
+    rng: &mut impl Rng,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut reservoir: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut line_num = 0;
+    loop {
+        let mut line = Vec::new();
+        if fh.read_until(delimiter, &mut line)? == 0 { break; }
+        if reservoir.len() < sample.num {
+            reservoir.push((line_num, line));
+        } else {
+            let slot = rng.gen_range(0..=line_num);
+            if slot < sample.num {
+                reservoir[slot] = (line_num, line);
+            }
+        }
+        line_num += 1;
+    }
+    // slot order is not itself random, so shuffle unless order is wanted
+    if sample.keep_order {
+        reservoir.sort_unstable_by_key(|(line_num, _)| *line_num);
+    } else {
+        reservoir.shuffle(rng);
+    }
+    for (_, line) in reservoir {
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_head_sample() {
+    let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+    let head = |num, seed, keep_order| {
+        let sample = Sample { num, seed: Some(seed), keep_order };
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut out = Vec::new();
+        head_sample(input.as_bytes(), &sample, b'\n', &mut rng, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    // same seed, same sample
+    assert_eq!(head(5, 1, false), head(5, 1, false));
+    let lines: Vec<usize> = head(5, 1, true).lines().map(|l| l.parse().unwrap()).collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines.windows(2).all(|w| w[0] < w[1]));
+    // asking for more lines than there are returns all of them
+    assert_eq!(head(200, 7, true), input);
+    assert_eq!(head(0, 7, false), "");
+}
+
+// Count records rather than physical lines so quoted fields with embedded
+// newlines stay whole; records are re-written with the same delimiter
+fn head_records(
+    fh: impl BufRead,
+    delimiter: u8,
+    keep_header: bool,
+    count: Count,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(fh);
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_writer(out);
+    let mut records = reader.byte_records();
+    if keep_header {
+        if let Some(header) = records.next() {
+            writer.write_byte_record(&header?)?;
+        }
+    }
+    match count {
+        Count::First(num) => {
+            for record in records.take(num) {
+                writer.write_byte_record(&record?)?;
+            }
+        }
+        Count::AllButLast(num) => {
+            let mut held = VecDeque::new();
+            for record in records {
+                if held.len() == num {
+                    writer.write_byte_record(&held.pop_front().unwrap())?;
+                }
+                held.push_back(record?);
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[test]
+fn test_head_records() {
+    let head = |input: &str, delimiter, keep_header, count| {
+        let mut out = Vec::new();
+        head_records(input.as_bytes(), delimiter, keep_header, count, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let csv = "name,note\na,\"one\ntwo\"\nb,plain\nc,\"x, y\"\n";
+
+    assert_eq!(head(csv, b',', false, Count::First(2)), "name,note\na,\"one\ntwo\"\n");
+    assert_eq!(
+        head(csv, b',', true, Count::First(2)),
+        "name,note\na,\"one\ntwo\"\nb,plain\n"
+    );
+    assert_eq!(head(csv, b',', true, Count::First(0)), "name,note\n");
+    assert_eq!(
+        head(csv, b',', true, Count::AllButLast(2)),
+        "name,note\na,\"one\ntwo\"\n"
+    );
+    assert_eq!(head("a\tb c\n1\t2\n", b'\t', false, Count::First(1)), "a\tb c\n");
+    assert_eq!(head("", b',', true, Count::First(5)), "");
+}
+
+fn head_pattern(
+    mut fh: impl BufRead,
+    pattern: &Pattern,
+    delimiter: u8,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut line = Vec::new();
+    let mut printed = 0;
+    while pattern.max_lines.is_none_or(|max| printed < max) {
+        line.clear();
+        if fh.read_until(delimiter, &mut line)? == 0 { break; }
+        let text = line.strip_suffix(&[delimiter]).unwrap_or(&line);
+        if pattern.regex.is_match(text) == pattern.until {
+            if pattern.inclusive {
+                out.write_all(&line)?;
             }
+            break;
+        }
+        out.write_all(&line)?;
+        printed += 1;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_head_pattern() {
+    let head = |input: &str, re: &str, until: bool, inclusive: bool, max_lines| {
+        let pattern = Pattern {
+            regex: Regex::new(re).unwrap(),
+            until,
+            inclusive,
+            max_lines,
         };
+        let mut out = Vec::new();
+        head_pattern(input.as_bytes(), &pattern, b'\n', &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let doc = "---\ntitle: x\n---\nbody\n";
+
+    assert_eq!(head(doc, "^title", true, false, None), "---\n");
+    assert_eq!(head(doc, "^title", true, true, None), "---\ntitle: x\n");
+    assert_eq!(head(doc, "^body$", true, false, Some(2)), "---\ntitle: x\n");
+    assert_eq!(head(doc, "^nomatch", true, false, None), doc);
+    assert_eq!(head("# a\n# b\ncode\n", "^#", false, false, None), "# a\n# b\n");
+    assert_eq!(
+        head("# a\n# b\ncode\n", "^#", false, true, None),
+        "# a\n# b\ncode\n"
+    );
+    assert_eq!(head("# a\n# b\ncode\n", "^#", false, false, Some(1)), "# a\n");
+    // the line terminator is not part of what is matched
+    assert_eq!(head("a\nb\n", "a$", true, false, None), "");
+}
+
+// Print all but the last num bytes, holding back no more than num bytes
+fn head_bytes_but_last(
+    mut fh: impl BufRead,
+    num: usize,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut held: VecDeque<u8> = VecDeque::new();
+    loop {
+        let buf = fh.fill_buf()?;
+        if buf.is_empty() { break; }
+        let len = buf.len();
+        held.extend(buf);
+        fh.consume(len);
+        if held.len() > num {
+            let excess = held.len() - num;
+            let (front, back) = held.as_slices();
+            let front_len = excess.min(front.len());
+            out.write_all(&front[..front_len])?;
+            out.write_all(&back[..excess - front_len])?;
+            held.drain(..excess);
+        }
+    }
+    Ok(())
+}
+
+// Print the first num characters. A character starts at any byte that is
+// not a UTF-8 continuation byte, so invalid input still comes out raw.
+fn head_chars(
+    mut fh: impl BufRead,
+    num: usize,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut seen = 0;
+    loop {
+        let buf = fh.fill_buf()?;
+        if buf.is_empty() { break; }
+        let mut end = buf.len();
+        for (i, &b) in buf.iter().enumerate() {
+            if b & 0xc0 != 0x80 {
+                if seen == num {
+                    end = i;
+                    break;
+                }
+                seen += 1;
+            }
+        }
+        out.write_all(&buf[..end])?;
+        if end < buf.len() { break; }
+        fh.consume(end);
+    }
+    Ok(())
+}
+
+// Print the first num extended grapheme clusters. The last cluster in
+// each block is held back, since more combining marks may follow. Each
+// invalid UTF-8 sequence counts as one cluster and is printed raw.
+fn head_graphemes(
+    mut fh: impl BufRead,
+    num: usize,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut remaining = num;
+    let mut buf = Vec::new();
+    while remaining > 0 {
+        let chunk = fh.fill_buf()?;
+        let eof = chunk.is_empty();
+        let len = chunk.len();
+        buf.extend_from_slice(chunk);
+        fh.consume(len);
+
+        let mut start = 0;
+        while remaining > 0 && start < buf.len() {
+            let (valid_len, invalid_len) = match str::from_utf8(&buf[start..]) {
+                Ok(text) => (text.len(), None),
+                Err(e) => (e.valid_up_to(), e.error_len()),
+            };
+            let text = str::from_utf8(&buf[start..start + valid_len]).unwrap();
+            // the text is complete if something definite follows it
+            let complete = eof || invalid_len.is_some();
+            let bounds: Vec<usize> =
+                text.grapheme_indices(true).map(|(i, _)| i).collect();
+            let usable = if complete {
+                bounds.len()
+            } else {
+                bounds.len().saturating_sub(1)
+            };
+            let take = usable.min(remaining);
+            let end = bounds.get(take).copied().unwrap_or(text.len());
+            out.write_all(&buf[start..start + end])?;
+            remaining -= take;
+            start += end;
+            if remaining == 0 || !complete {
+                break;
+            }
+            // an invalid sequence, or an incomplete one at end of input
+            let bad_len = invalid_len.unwrap_or(buf.len() - start);
+            out.write_all(&buf[start..start + bad_len])?;
+            remaining -= 1;
+            start += bad_len;
+        }
+        buf.drain(..start);
+        if eof { break; }
     }
     Ok(())
 }
 
+#[test]
+fn test_head_chars_graphemes() {
+    let head = |f: fn(&[u8], usize, &mut Vec<u8>) -> MyResult<()>,
+                input: &[u8],
+                num: usize| {
+        let mut out = Vec::new();
+        f(input, num, &mut out).unwrap();
+        out
+    };
+    let chars = |input: &[u8], num, out: &mut Vec<u8>| head_chars(input, num, out);
+    let graphemes =
+        |input: &[u8], num, out: &mut Vec<u8>| head_graphemes(input, num, out);
+
+    assert_eq!(head(chars, b"", 2), b"");
+    assert_eq!(head(chars, "Ése".as_bytes(), 1), "É".as_bytes());
+    assert_eq!(head(chars, "Ése".as_bytes(), 2), "És".as_bytes());
+    assert_eq!(head(chars, "Ése".as_bytes(), 9), "Ése".as_bytes());
+    assert_eq!(head(chars, b"\xffab", 2), b"\xffa");
+    // a combining accent is a character of its own
+    assert_eq!(head(chars, "e\u{301}x".as_bytes(), 1), b"e");
+
+    assert_eq!(head(graphemes, b"", 2), b"");
+    assert_eq!(
+        head(graphemes, "e\u{301}x".as_bytes(), 1),
+        "e\u{301}".as_bytes()
+    );
+    assert_eq!(
+        head(graphemes, "🇨🇦🇺🇸!".as_bytes(), 1),
+        "🇨🇦".as_bytes()
+    );
+    assert_eq!(head(graphemes, "a\r\nb".as_bytes(), 2), b"a\r\n");
+    assert_eq!(head(graphemes, b"a\xffb", 2), b"a\xff");
+    assert_eq!(head(graphemes, b"a\xe2\x82", 5), b"a\xe2\x82");
+}
+
+#[test]
+fn test_head_graphemes_across_blocks() {
+    // a reader that hands out one byte at a time
+    let input = "ne\u{301}e\u{301}!".as_bytes();
+    let reader = BufReader::with_capacity(1, input);
+    let mut out = Vec::new();
+    head_graphemes(reader, 2, &mut out).unwrap();
+    assert_eq!(out, "ne\u{301}".as_bytes());
+}
+
 fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
     match filename {
         "-" => Ok(Box::new(BufReader::new(io::stdin()))),
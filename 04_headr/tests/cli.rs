@@ -1,3 +1,6 @@
+// the original tests pass argument arrays by reference
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
@@ -93,17 +96,16 @@ fn skips_bad_file() -> TestResult {
 
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
+    // Bytes are passed through raw, so compare raw bytes
     let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    let mut expected = Vec::new();
+    file.read_to_end(&mut expected)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
         .success()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected.as_slice()));
 
     Ok(())
 }
@@ -114,18 +116,17 @@ fn run_stdin(
     input_file: &str,
     expected_file: &str,
 ) -> TestResult {
-    // Extra work here due to lossy UTF
+    // Bytes are passed through raw, so compare raw bytes
     let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    let mut expected = Vec::new();
+    file.read_to_end(&mut expected)?;
     let input = fs::read_to_string(input_file)?;
 
     Command::cargo_bin(PRG)?
         .write_stdin(input)
         .args(args)
         .assert()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected.as_slice()));
 
     Ok(())
 }
@@ -419,3 +420,234 @@ fn multiple_files_c4() -> TestResult {
         "tests/expected/all.c4.out",
     )
 }
+
+// --------------------------------------------------
+#[test]
+fn all_but_last_lines() -> TestResult {
+    for file in &[EMPTY, ONE, TWO, THREE, TEN] {
+        let expected = file.replace("inputs", "expected") + ".n-2.out";
+        run(&[file, "-n", "-2"], &expected)?;
+        run_stdin(&["-n", "-2"], file, &expected)?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn all_but_last_bytes() -> TestResult {
+    for file in &[EMPTY, ONE, TWO, THREE, TEN] {
+        let expected = file.replace("inputs", "expected") + ".c-4.out";
+        run(&[file, "-c", "-4"], &expected)?;
+        run_stdin(&["--bytes=-4"], file, &expected)?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_all_but_last() -> TestResult {
+    run(
+        &[EMPTY, ONE, TWO, THREE, TEN, "-n", "-2"],
+        "tests/expected/all.n-2.out",
+    )?;
+    run(
+        &["-c", "-4", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.c-4.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn size_suffixes() -> TestResult {
+    run(&[TEN, "-n", "1K"], "tests/expected/ten.txt.out")?;
+    run(&[TEN, "-c", "1KB"], TEN)?;
+    run(&[TEN, "-n", "-1b"], "tests/expected/empty.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_count_too_large() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1Y", EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "illegal byte count -- 1Y: value too large",
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_are_raw() -> TestResult {
+    let input: Vec<u8> = (0..=255).collect();
+    Command::cargo_bin(PRG)?
+        .args(["-c", "200"])
+        .write_stdin(input.clone())
+        .assert()
+        .success()
+        .stdout(predicate::eq(&input[..200]));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn chars_and_graphemes() -> TestResult {
+    // "Ö" is a single character, so the cut never lands inside it
+    run(&[ONE, "--chars", "1"], "tests/expected/one.txt.chars1.out")?;
+    run_stdin(&["--graphemes", "3"], ONE, "tests/expected/one.txt.graphemes3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn header_control() -> TestResult {
+    run(&["-v", ONE], "tests/expected/one.txt.v.out")?;
+    run(
+        &["--quiet", "-n", "2", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.q.n2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_quiet_and_verbose() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "-v", ONE])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn header_format() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "--header-format", "--- %f ---", ONE, TWO])
+        .assert()
+        .success()
+        .stdout(format!(
+            "--- {} ---\nÖne line, four words.\n\n--- {} ---\nTwo lines.\n",
+            ONE, TWO
+        ));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_terminated() -> TestResult {
+    const ZERO: &str = "./tests/inputs/zero.txt";
+    run(&["-z", "-n", "2", ZERO], "tests/expected/zero.txt.z.n2.out")?;
+    run(&["-z", "-n", "-1", ZERO], "tests/expected/zero.txt.z.n-1.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn until_and_while() -> TestResult {
+    run(&["--until", "^four$", TEN], "tests/expected/ten.txt.until-four.out")?;
+    run(
+        &["--until", "^four$", "--inclusive", "-n", "2", TEN],
+        "tests/expected/ten.txt.until-four.inclusive.n2.out",
+    )?;
+    run(&["--while", "o", "--inclusive", TEN], "tests/expected/ten.txt.while-o.out")?;
+    run(&["--until", "nomatch", THREE], "tests/expected/three.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_until() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--until", "(", TEN])
+        .assert()
+        .failure()
+        .stderr("Invalid --until \"(\"\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_records() -> TestResult {
+    const DATA: &str = "./tests/inputs/data.csv";
+    run(&["--csv", "-n", "2", DATA], "tests/expected/data.csv.csv.n2.out")?;
+    run(
+        &["--csv", "--keep-header", "-n", "2", DATA],
+        "tests/expected/data.csv.csv.keep-header.n2.out",
+    )?;
+    run_stdin(&["--tsv", "-n", "2"], TEN, "tests/expected/ten.txt.n2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn sample() -> TestResult {
+    // sampling every line with --keep-order gives the input back
+    run(&["--sample", "10", "--keep-order", TEN], "tests/expected/ten.txt.out")?;
+
+    // the same seed picks the same lines
+    let sample = |seed: &str| -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = Command::cargo_bin(PRG)?
+            .args(["--sample", "3", "--seed", seed, TEN])
+            .output()?;
+        Ok(output.stdout)
+    };
+    let first = sample("42")?;
+    assert_eq!(first, sample("42")?);
+    assert_eq!(String::from_utf8(first)?.lines().count(), 3);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_seed() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--sample", "3", "--seed", "x", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal seed -- x\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn huge_negative_count() -> TestResult {
+    for args in [["-n", "-1E"], ["-n", "-1G"]] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .write_stdin("a\nb\n")
+            .assert()
+            .success()
+            .stdout("");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn huge_sample() -> TestResult {
+    for num in ["1E", "1G"] {
+        Command::cargo_bin(PRG)?
+            .args(["--sample", num, "--keep-order"])
+            .write_stdin("a\nb\n")
+            .assert()
+            .success()
+            .stdout("a\nb\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn huge_negative_record_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--csv", "-n", "-1E"])
+        .write_stdin("a,b\n1,2\n")
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
@@ -1,17 +1,104 @@
 use std::error::Error;
 use clap::{App, Arg};
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use csv::WriterBuilder;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+use walkdir::{DirEntry, WalkDir};
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
+const BUF_SIZE: usize = 64 * 1024;
+const MAX_UTF8_LEN: usize = 6;
+// with --jobs, regular files larger than this are counted in pieces
+const SPLIT_SIZE: u64 = 16 * 1024 * 1024;
+// how many byte values --stats lists, and how wide its histogram bars are
+const COMMON_BYTES: usize = 5;
+const HISTOGRAM_WIDTH: usize = 40;
+
 #[derive(Debug)]
 pub struct Config {
-    files: Vec<String>,
+    files: Vec<PathBuf>,
     lines: bool,
     words: bool,
     bytes: bool,
     chars: bool,
+    max_line_length: bool,
+    word_mode: WordMode,
+    patterns: Vec<Regex>,
+    pattern_mode: PatternMode,
+    jobs: usize,
+    total: Total,
+    names: bool,
+    // the file list came from a pipe, so there is nothing to size the columns by
+    streamed_list: bool,
+    format: Format,
+    stats: bool,
+    recursive: bool,
+    languages: Vec<Language>,
+}
+
+// How words are told apart
+#[derive(Debug, Clone, Default)]
+enum WordMode {
+    // runs of printable characters between spaces, as GNU wc counts them
+    #[default]
+    Gnu,
+    // UAX #29 word segments that contain a letter or number
+    Unicode,
+    // non-empty matches of a pattern
+    Regex(Regex),
+}
+
+// What --count-pattern counts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum PatternMode {
+    // non-overlapping, non-empty matches
+    #[default]
+    Matches,
+    // lines with a non-empty match, like grep -c but ignoring empty matches
+    Lines,
+}
+
+// Everything count needs to know besides the input itself
+#[derive(Debug, Clone, Default)]
+struct CountOptions {
+    decode: bool,
+    word_mode: WordMode,
+    patterns: Vec<Regex>,
+    pattern_mode: PatternMode,
+    stats: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+    Tsv,
+}
+
+// When to print the total row
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Total {
+    Auto,
+    Always,
+    Only,
+    Never,
 }
 
 #[derive(Debug)]
@@ -21,6 +108,476 @@ struct Data {
     words: usize,
     bytes: usize,
     chars: usize,
+    max_line_length: usize,
+    // one count for each --count-pattern
+    patterns: Vec<usize>,
+    stats: Option<LineStats>,
+}
+
+// What --stats reports about the lines and bytes of an input. Lengths are
+// in bytes, not counting the line ending.
+#[derive(Debug, Clone, Default)]
+struct LineStats {
+    // number of lines of each length
+    lengths: BTreeMap<usize, usize>,
+    empty: usize,
+    trailing_whitespace: usize,
+    cr_endings: usize,
+    // indexed by byte value, empty until the first byte is seen
+    byte_counts: Vec<usize>,
+    missing_final_newline: bool,
+    // the unfinished last line and its last two bytes
+    line_len: usize,
+    line_tail: Vec<u8>,
+}
+
+impl LineStats {
+    fn update(&mut self, chunk: &[u8]) {
+        if self.byte_counts.is_empty() {
+            self.byte_counts = vec![0; 256];
+        }
+        for &byte in chunk {
+            self.byte_counts[byte as usize] += 1;
+        }
+        for (i, segment) in chunk.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                self.end_line();
+            }
+            self.line_len += segment.len();
+            self.line_tail.extend_from_slice(&segment[segment.len().saturating_sub(2)..]);
+            let excess = self.line_tail.len().saturating_sub(2);
+            self.line_tail.drain(..excess);
+        }
+    }
+
+    fn end_line(&mut self) {
+        let mut len = self.line_len;
+        let mut tail = &self.line_tail[..];
+        if let [rest @ .., b'\r'] = tail {
+            self.cr_endings += 1;
+            len -= 1;
+            tail = rest;
+        }
+        if len == 0 {
+            self.empty += 1;
+        } else if matches!(tail.last(), Some(b' ' | b'\t')) {
+            self.trailing_whitespace += 1;
+        }
+        *self.lengths.entry(len).or_default() += 1;
+        self.line_len = 0;
+        self.line_tail.clear();
+    }
+
+    fn finish(&mut self) {
+        if self.line_len > 0 {
+            self.missing_final_newline = true;
+            self.end_line();
+        }
+    }
+
+    fn add(&mut self, other: &LineStats) {
+        for (&len, &count) in &other.lengths {
+            *self.lengths.entry(len).or_default() += count;
+        }
+        self.empty += other.empty;
+        self.trailing_whitespace += other.trailing_whitespace;
+        self.cr_endings += other.cr_endings;
+        if self.byte_counts.is_empty() {
+            self.byte_counts = vec![0; 256];
+        }
+        for (count, other) in self.byte_counts.iter_mut().zip(&other.byte_counts) {
+            *count += other;
+        }
+        self.missing_final_newline |= other.missing_final_newline;
+    }
+
+    fn summary(&self) -> StatsSummary {
+        let lines: usize = self.lengths.values().sum();
+        let total: usize = self.lengths.iter().map(|(len, count)| len * count).sum();
+        let (mut mean, mut median, mut p90, mut p99) = (None, None, None, None);
+        if lines > 0 {
+            mean = Some(total as f64 / lines as f64);
+            median = Some((self.nth((lines - 1) / 2) + self.nth(lines / 2)) as f64 / 2.0);
+            p90 = Some(self.percentile(90, lines));
+            p99 = Some(self.percentile(99, lines));
+        }
+
+        // lengths 0, 1, 2-3, 4-7 and so on
+        let mut histogram: Vec<Bucket> = Vec::new();
+        for (&len, &count) in &self.lengths {
+            let (from, to) = match len {
+                0 => (0, 0),
+                _ => {
+                    let from = 1 << len.ilog2();
+                    (from, 2 * from - 1)
+                }
+            };
+            match histogram.last_mut() {
+                Some(bucket) if bucket.from == from => bucket.lines += count,
+                _ => {
+                    // empty buckets in between keep the scale readable
+                    let mut next = histogram.last().map_or(from, |b| b.to + 1);
+                    while next < from {
+                        histogram.push(Bucket { from: next, to: 2 * next - 1, lines: 0 });
+                        next *= 2;
+                    }
+                    histogram.push(Bucket { from, to, lines: count });
+                }
+            }
+        }
+
+        let mut common_bytes: Vec<ByteCount> = self
+            .byte_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(byte, &count)| ByteCount { byte: byte as u8, count })
+            .collect();
+        common_bytes.sort_by(|a, b| b.count.cmp(&a.count).then(a.byte.cmp(&b.byte)));
+        common_bytes.truncate(COMMON_BYTES);
+
+        StatsSummary {
+            lines,
+            empty: self.empty,
+            trailing_whitespace: self.trailing_whitespace,
+            cr_endings: self.cr_endings,
+            missing_final_newline: self.missing_final_newline,
+            min: self.lengths.keys().next().copied(),
+            max: self.lengths.keys().next_back().copied(),
+            mean,
+            median,
+            p90,
+            p99,
+            histogram,
+            common_bytes,
+        }
+    }
+
+    // the length of the line at index n in sorted order
+    fn nth(&self, n: usize) -> usize {
+        let mut seen = 0;
+        for (&len, &count) in &self.lengths {
+            seen += count;
+            if seen > n {
+                return len;
+            }
+        }
+        0
+    }
+
+    // nearest-rank percentile
+    fn percentile(&self, p: usize, lines: usize) -> usize {
+        self.nth((p * lines).div_ceil(100).max(1) - 1)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct StatsSummary {
+    lines: usize,
+    empty: usize,
+    trailing_whitespace: usize,
+    cr_endings: usize,
+    missing_final_newline: bool,
+    min: Option<usize>,
+    max: Option<usize>,
+    mean: Option<f64>,
+    median: Option<f64>,
+    p90: Option<usize>,
+    p99: Option<usize>,
+    histogram: Vec<Bucket>,
+    common_bytes: Vec<ByteCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Bucket {
+    from: usize,
+    to: usize,
+    lines: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ByteCount {
+    byte: u8,
+    count: usize,
+}
+
+// Comment syntax of a language, keyed by file extension or exact name
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Language {
+    #[serde(skip)]
+    name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    filenames: Vec<String>,
+    #[serde(default)]
+    line_comment: Vec<String>,
+    #[serde(default)]
+    block_comment: Vec<(String, String)>,
+}
+
+// One row of the built-in language table, see Language
+struct LanguageDef {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    filenames: &'static [&'static str],
+    line_comment: &'static [&'static str],
+    block_comment: &'static [(&'static str, &'static str)],
+}
+
+const NO_LANGUAGE: LanguageDef = LanguageDef {
+    name: "",
+    extensions: &[],
+    filenames: &[],
+    line_comment: &[],
+    block_comment: &[],
+};
+
+const LANGUAGES: &[LanguageDef] = &[
+    LanguageDef {
+        name: "Bash",
+        extensions: &["sh", "bash"],
+        line_comment: &["#"],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "C",
+        extensions: &["c", "h"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "C++",
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "CSS",
+        extensions: &["css"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Go",
+        extensions: &["go"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "HTML",
+        extensions: &["htm", "html"],
+        block_comment: &[("<!--", "-->")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Haskell",
+        extensions: &["hs"],
+        line_comment: &["--"],
+        block_comment: &[("{-", "-}")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Java",
+        extensions: &["java"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "JavaScript",
+        extensions: &["cjs", "js", "mjs"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "JSON",
+        extensions: &["json"],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Lua",
+        extensions: &["lua"],
+        line_comment: &["--"],
+        block_comment: &[("--[[", "]]")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Makefile",
+        extensions: &["mk"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        line_comment: &["#"],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Markdown",
+        extensions: &["md"],
+        block_comment: &[("<!--", "-->")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Python",
+        extensions: &["py"],
+        line_comment: &["#"],
+        block_comment: &[("\"\"\"", "\"\"\""), ("'''", "'''")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Ruby",
+        extensions: &["rb"],
+        filenames: &["Rakefile", "Gemfile"],
+        line_comment: &["#"],
+        block_comment: &[("=begin", "=end")],
+    },
+    LanguageDef {
+        name: "Rust",
+        extensions: &["rs"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "SQL",
+        extensions: &["sql"],
+        line_comment: &["--"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "Text",
+        extensions: &["txt"],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "TOML",
+        extensions: &["toml"],
+        line_comment: &["#"],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "TypeScript",
+        extensions: &["ts", "tsx"],
+        line_comment: &["//"],
+        block_comment: &[("/*", "*/")],
+        ..NO_LANGUAGE
+    },
+    LanguageDef {
+        name: "YAML",
+        extensions: &["yaml", "yml"],
+        line_comment: &["#"],
+        ..NO_LANGUAGE
+    },
+];
+
+// The built-in table, with languages from a TOML file replacing or adding
+// to it. The file has one table per language, for example:
+//
+//     [Rust]
+//     extensions = ["rs"]
+//     line_comment = ["//"]
+//     block_comment = [["/*", "*/"]]
+fn load_languages(config_file: Option<&str>) -> MyResult<Vec<Language>> {
+    let mut languages: Vec<Language> = Vec::new();
+    if let Some(path) = config_file {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let table: BTreeMap<String, Language> =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        for (name, language) in table {
+            // an empty marker would match everywhere without moving on
+            let markers = language.block_comment.iter().flat_map(|(start, end)| [start, end]);
+            if language.line_comment.iter().chain(markers).any(String::is_empty) {
+                return Err(From::from(format!("{}: empty comment marker for {}", path, name)));
+            }
+            languages.push(Language { name, ..language });
+        }
+    }
+    for def in LANGUAGES {
+        if languages.iter().any(|language| language.name == def.name) {
+            continue;
+        }
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        languages.push(Language {
+            name: def.name.to_string(),
+            extensions: strings(def.extensions),
+            filenames: strings(def.filenames),
+            line_comment: strings(def.line_comment),
+            block_comment: def
+                .block_comment
+                .iter()
+                .map(|(start, end)| (start.to_string(), end.to_string()))
+                .collect(),
+        });
+    }
+    Ok(languages)
+}
+
+// Lines of code for one language, or for all of them in the total
+#[derive(Debug, Default, Serialize)]
+struct LanguageData {
+    // empty on the total row
+    language: String,
+    total: bool,
+    files: usize,
+    blank: usize,
+    comment: usize,
+    code: usize,
+}
+
+impl LanguageData {
+    fn add(&mut self, other: &LanguageData) {
+        self.files += other.files;
+        self.blank += other.blank;
+        self.comment += other.comment;
+        self.code += other.code;
+    }
+}
+
+// One row of structured output; field names are part of the interface
+#[derive(Serialize)]
+struct Record<'a> {
+    // empty on the total row
+    filename: &'a str,
+    total: bool,
+    lines: usize,
+    words: usize,
+    bytes: usize,
+    chars: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_line_length: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    patterns: Vec<PatternCount<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<StatsSummary>,
+}
+
+#[derive(Serialize)]
+struct PatternCount<'a> {
+    pattern: &'a str,
+    count: usize,
+}
+
+impl Data {
+    // the longest line of several inputs is the longest of any of them
+    fn add(&mut self, other: &Data) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.max_line_length = self.max_line_length.max(other.max_line_length);
+        if self.patterns.len() < other.patterns.len() {
+            self.patterns.resize(other.patterns.len(), 0);
+        }
+        for (count, other) in self.patterns.iter_mut().zip(&other.patterns) {
+            *count += other;
+        }
+        if let (Some(stats), Some(other)) = (self.stats.as_mut(), &other.stats) {
+            stats.add(other);
+        }
+    }
 }
 
 pub fn get_args() -> MyResult<Config> {
@@ -35,6 +592,21 @@ pub fn get_args() -> MyResult<Config> {
         .default_value("-")
         .min_values(0),
     )
+    .arg(
+        Arg::with_name("files0_from")
+        .value_name("F")
+        .long("files0-from")
+        .help("read input from the files named by NUL-terminated names in F, or - for STDIN")
+        .takes_value(true)
+        .conflicts_with("files_from"),
+    )
+    .arg(
+        Arg::with_name("files_from")
+        .value_name("F")
+        .long("files-from")
+        .help("read input from the files named one per line in F, or - for STDIN")
+        .takes_value(true),
+    )
     .arg(
         Arg::with_name("lines")
         .value_name("LINES")
@@ -45,12 +617,45 @@ pub fn get_args() -> MyResult<Config> {
     )
     .arg(
         Arg::with_name("words")
-        .value_name("WORDS")
         .short("w")
-        .long("words")
         .help("print the word counts")
         .takes_value(false),
     )
+    // a separate argument so that -w still bundles with other flags
+    .arg(
+        Arg::with_name("word_mode")
+        .value_name("MODE")
+        .long("words")
+        .help("print the word counts, optionally of unicode words")
+        .possible_values(&["gnu", "unicode"])
+        .min_values(0)
+        .max_values(1)
+        .require_equals(true),
+    )
+    .arg(
+        Arg::with_name("word_regex")
+        .value_name("PATTERN")
+        .long("word-regex")
+        .help("print the word counts, where a word is a match of PATTERN")
+        .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("count_pattern")
+        .value_name("PATTERN")
+        .long("count-pattern")
+        .help("print the number of matches of PATTERN, once per pattern")
+        .multiple(true)
+        .number_of_values(1)
+        .conflicts_with("recursive"),
+    )
+    .arg(
+        Arg::with_name("pattern_mode")
+        .value_name("MODE")
+        .long("pattern-mode")
+        .help("count every match of a --count-pattern, or the lines with one")
+        .possible_values(&["matches", "lines"])
+        .default_value("matches"),
+    )
     .arg(
         Arg::with_name("bytes")
         .value_name("BYTES")
@@ -68,110 +673,1346 @@ pub fn get_args() -> MyResult<Config> {
         .conflicts_with("bytes")
         .takes_value(false),
     )
+    .arg(
+        Arg::with_name("max_line_length")
+        .short("L")
+        .long("max-line-length")
+        .help("print the maximum display width")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("total")
+        .value_name("WHEN")
+        .long("total")
+        .help("when to print a line with total counts")
+        .possible_values(&["auto", "always", "only", "never"])
+        .default_value("auto"),
+    )
+    .arg(
+        Arg::with_name("format")
+        .value_name("FORMAT")
+        .long("format")
+        .help("output format")
+        .possible_values(&["text", "json", "jsonl", "csv", "tsv"])
+        .default_value("text"),
+    )
+    .arg(
+        Arg::with_name("stats")
+        .long("stats")
+        .help("report the distribution of line lengths and other line statistics")
+        .takes_value(false)
+        .conflicts_with("recursive"),
+    )
+    .arg(
+        Arg::with_name("recursive")
+        .short("r")
+        .long("recursive")
+        .help("count blank, comment and code lines per language in directories")
+        .takes_value(false)
+        .conflicts_with_all(&[
+            "lines",
+            "words",
+            "word_mode",
+            "word_regex",
+            "bytes",
+            "chars",
+            "max_line_length",
+            "jobs",
+        ]),
+    )
+    .arg(
+        Arg::with_name("lang_config")
+        .value_name("FILE")
+        .long("lang-config")
+        .help("TOML file of language comment syntax for --recursive")
+        .takes_value(true)
+        .requires("recursive"),
+    )
+    .arg(
+        Arg::with_name("jobs")
+        .value_name("N")
+        .short("j")
+        .long("jobs")
+        .help("count with N threads, 0 for one per CPU")
+        .default_value("1"),
+    )
     .get_matches();
 
     let mut lines = matches.is_present("lines");
-    let mut words = matches.is_present("words");
+    let word_mode = match (matches.value_of("word_mode"), matches.value_of("word_regex")) {
+        (Some("unicode"), Some(_)) => {
+            return Err(From::from("--word-regex cannot be used with --words=unicode"));
+        }
+        (Some("unicode"), None) => WordMode::Unicode,
+        (_, Some(pattern)) => WordMode::Regex(
+            Regex::new(pattern).map_err(|_| format!("Invalid --word-regex \"{}\"", pattern))?,
+        ),
+        _ => WordMode::Gnu,
+    };
+    let mut words = ["words", "word_mode", "word_regex"]
+        .iter()
+        .any(|arg| matches.is_present(arg));
+    let patterns = matches
+        .values_of("count_pattern")
+        .unwrap_or_default()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|_| format!("Invalid --count-pattern \"{}\"", pattern))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
     let mut bytes = matches.is_present("bytes");
     let chars = matches.is_present("chars");
+    let max_line_length = matches.is_present("max_line_length");
 
-    if [lines, words, bytes, chars].iter().all(|v| v==&false) {
+    if [lines, words, bytes, chars, max_line_length].iter().all(|v| v==&false) {
         lines = true;
         words = true;
         bytes = true;
     }
 
+    let operands = matches.occurrences_of("files") > 0;
+    let list = match (matches.value_of("files0_from"), matches.value_of("files_from")) {
+        (Some(source), _) => Some(("--files0-from", source, b'\0')),
+        (_, Some(source)) => Some(("--files-from", source, b'\n')),
+        _ => None,
+    };
+    let (files, names, streamed_list) = match list {
+        Some((option, _, _)) if operands => {
+            return Err(From::from(format!("file operands cannot be combined with {}", option)));
+        }
+        Some((_, source, separator)) => {
+            let listed = if source == "-" { Path::new("/dev/stdin") } else { Path::new(source) };
+            let streamed = !fs::metadata(listed).map(|m| m.is_file()).unwrap_or(false);
+            (read_file_list(source, separator)?, true, streamed)
+        }
+        // stdin is only named when given as an operand
+        None => (matches.values_of_os("files").unwrap().map(PathBuf::from).collect(), operands, false),
+    };
+
+    let recursive = matches.is_present("recursive");
+    let languages = if recursive {
+        load_languages(matches.value_of("lang_config"))?
+    } else {
+        Vec::new()
+    };
+
+    let jobs = matches.value_of("jobs").unwrap();
+    let jobs = match jobs.parse() {
+        Ok(0) => thread::available_parallelism().map_or(1, |n| n.get()),
+        Ok(n) => n,
+        Err(_) => return Err(From::from(format!("invalid number of jobs -- {}", jobs))),
+    };
+
     Ok(Config {
-        files: matches.values_of_lossy("files").unwrap(),
+        files,
         lines,
         words,
         bytes,
         chars,
+        max_line_length,
+        word_mode,
+        patterns,
+        pattern_mode: match matches.value_of("pattern_mode") {
+            Some("lines") => PatternMode::Lines,
+            _ => PatternMode::Matches,
+        },
+        jobs,
+        total: match matches.value_of("total") {
+            Some("always") => Total::Always,
+            Some("only") => Total::Only,
+            Some("never") => Total::Never,
+            _ => Total::Auto,
+        },
+        names,
+        streamed_list,
+        format: match matches.value_of("format") {
+            Some("json") => Format::Json,
+            Some("jsonl") => Format::Jsonl,
+            Some("csv") => Format::Csv,
+            Some("tsv") => Format::Tsv,
+            _ => Format::Text,
+        },
+        stats: matches.is_present("stats"),
+        recursive,
+        languages,
     })
 }
 
+// The file names listed in source, which are separated by NUL bytes for
+// --files0-from and by newlines for --files-from. Blank lines are skipped,
+// but an empty NUL-terminated name is an error, as in GNU wc.
+fn read_file_list(source: &str, separator: u8) -> MyResult<Vec<PathBuf>> {
+    let mut list = Vec::new();
+    let mut file = open(Path::new(source)).map_err(|e| format!("cannot open '{}' for reading: {}", source, e))?;
+    file.read_to_end(&mut list).map_err(|e| format!("{}: {}", source, e))?;
+    if list.last() == Some(&separator) {
+        list.pop();
+    }
+    if list.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for (i, name) in list.split(|&b| b == separator).enumerate() {
+        if name.is_empty() {
+            if separator == b'\n' {
+                continue;
+            }
+            return Err(From::from(format!("{}:{}: invalid zero-length file name", source, i + 1)));
+        }
+        if source == "-" && name == b"-" {
+            return Err(From::from(
+                "when reading file names from stdin, no file name of '-' allowed",
+            ));
+        }
+        files.push(path_from_bytes(name));
+    }
+    Ok(files)
+}
+
+// Names need not be UTF-8, and findr -print0 passes them on as they are
+#[cfg(unix)]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(name))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).into_owned())
+}
+
 pub fn run(config: Config) -> MyResult<()> {
+    if config.recursive {
+        return run_recursive(&config);
+    }
+    let text = config.format == Format::Text;
+    // structured output always carries every count
+    let options = CountOptions {
+        decode: !text || config.words || config.chars || config.max_line_length,
+        word_mode: config.word_mode.clone(),
+        patterns: config.patterns.clone(),
+        pattern_mode: config.pattern_mode,
+        stats: config.stats,
+    };
+    let results = if config.jobs > 1 {
+        count_parallel(&config.files, &options, config.jobs)
+    } else {
+        config.files.iter().map(|filename| count_file(filename, &options)).collect()
+    };
     let mut data = Vec::new();
-    for filename in &config.files {
-        match open(&filename) {
-            Err(e) => eprintln!("Failed to open {}: {}", filename, e),
-            Ok(mut file) => data.push(count(&mut file, &filename).unwrap()),
+    for result in results {
+        match result {
+            Err(e) => eprintln!("{}", e),
+            Ok(mut datum) => {
+                if text && !config.names {
+                    datum.filename.clear();
+                }
+                data.push(datum);
+            }
         }
     }
+
+    // structured output marks the total row with a field instead of a name
+    // that a file could also have
     let mut sum = Data {
-        filename: "total".to_string(),
+        filename: if text && config.total != Total::Only { "total" } else { "" }.to_string(),
         lines: 0,
         words: 0,
         bytes: 0,
         chars: 0,
+        max_line_length: 0,
+        patterns: vec![0; config.patterns.len()],
+        stats: config.stats.then(LineStats::default),
     };
-    if data.len() > 1 {
-        for datum in &data {
-            sum.lines += datum.lines;
-            sum.words += datum.words;
-            sum.bytes += datum.bytes;
-            sum.chars += datum.chars;
+    for datum in &data {
+        sum.add(datum);
+    }
+    let files = match config.total {
+        Total::Only => &data[..0],
+        _ => &data[..],
+    };
+    let total = print_total(&config, config.files.len()).then_some(&sum);
+
+    match config.format {
+        Format::Text => {
+            let width = number_width(&config);
+            for row in files.iter().chain(total) {
+                println!("{}", print_data(&config, row, width)?);
+                if let Some(stats) = &row.stats {
+                    print!("{}", format_stats(&stats.summary()));
+                }
+            }
+        }
+        Format::Json | Format::Jsonl | Format::Csv | Format::Tsv => {
+            print_records(&config, files, total)?
+        }
+    }
+    Ok(())
+}
+
+fn print_total(config: &Config, rows: usize) -> bool {
+    match config.total {
+        Total::Auto => rows > 1,
+        Total::Always | Total::Only => true,
+        Total::Never => false,
+    }
+}
+
+// Sum up the files under each operand per language, cloc-style
+fn run_recursive(config: &Config) -> MyResult<()> {
+    let mut stats: BTreeMap<String, LanguageData> = BTreeMap::new();
+    let paths = if config.names { config.files.clone() } else { vec![PathBuf::from(".")] };
+    for path in &paths {
+        let entries = WalkDir::new(path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !is_vcs_dir(entry))
+            .filter_map(|e| match e {
+                Err(e) => {
+                    eprintln!("{}", e);
+                    None
+                }
+                Ok(entry) => Some(entry),
+            })
+            .filter(|entry| entry.file_type().is_file());
+        for entry in entries {
+            let filename = entry.file_name().to_string_lossy();
+            let extension = entry.path().extension().map(|e| e.to_string_lossy());
+            // files of unknown languages are grouped by extension, with
+            // every non-blank line counted as code
+            let unknown;
+            let language = match config.languages.iter().find(|language| {
+                language.filenames.iter().any(|name| *name == filename)
+                    || extension.as_ref().is_some_and(|ext| {
+                        language.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext))
+                    })
+            }) {
+                Some(language) => language,
+                None => match &extension {
+                    Some(ext) => {
+                        unknown = Language {
+                            name: format!(".{}", ext),
+                            ..Language::default()
+                        };
+                        &unknown
+                    }
+                    None => continue,
+                },
+            };
+            match count_code(entry.path(), language) {
+                Err(e) => eprintln!("{}: {}", entry.path().display(), e),
+                Ok(None) => {}
+                Ok(Some(data)) => stats
+                    .entry(language.name.clone())
+                    .or_insert_with(|| LanguageData {
+                        language: language.name.clone(),
+                        ..LanguageData::default()
+                    })
+                    .add(&data),
+            }
         }
     }
 
+    let mut data: Vec<LanguageData> = stats.into_values().collect();
+    data.sort_by(|a, b| b.code.cmp(&a.code).then_with(|| a.language.cmp(&b.language)));
+    let mut sum = LanguageData {
+        language: if config.format == Format::Text && config.total != Total::Only {
+            "total"
+        } else {
+            ""
+        }
+        .to_string(),
+        total: true,
+        ..LanguageData::default()
+    };
     for datum in &data {
-        println!("{}", print_data(&config, &datum)?);
+        sum.add(datum);
     }
-    if data.len() > 1 {
-        println!("{}", print_data(&config, &sum)?);
+    let mut rows: Vec<&LanguageData> = Vec::new();
+    if config.total != Total::Only {
+        rows.extend(&data);
     }
-    Ok(())
+    if print_total(config, data.len()) {
+        rows.push(&sum);
+    }
+
+    if config.format == Format::Text {
+        let header = ["files", "blank", "comment", "code"];
+        let widest = rows
+            .iter()
+            .flat_map(|row| [row.files, row.blank, row.comment, row.code])
+            .max()
+            .unwrap_or(0);
+        let width = widest.to_string().len().max(header.iter().map(|h| h.len()).max().unwrap());
+        let header = header.iter().map(|h| format!("{:>1$}", h, width)).collect::<Vec<_>>();
+        println!("{} language", header.join(" "));
+        for row in rows {
+            let mut line = [row.files, row.blank, row.comment, row.code]
+                .iter()
+                .map(|value| format!("{:>1$}", value, width))
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !row.language.is_empty() {
+                line.push_str(&format!(" {}", row.language));
+            }
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    print_structured(
+        config.format,
+        &["language", "total", "files", "blank", "comment", "code"],
+        &rows,
+        |row| {
+            vec![
+                row.language.clone(),
+                row.total.to_string(),
+                row.files.to_string(),
+                row.blank.to_string(),
+                row.comment.to_string(),
+                row.code.to_string(),
+            ]
+        },
+    )
+}
+
+fn is_vcs_dir(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir()
+        && matches!(entry.file_name().to_str(), Some(".git" | ".hg" | ".svn"))
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+// Blank, comment and code lines of one file; None for binary files
+fn count_code(path: &std::path::Path, language: &Language) -> MyResult<Option<LanguageData>> {
+    let bytes = fs::read(path)?;
+    if bytes[..bytes.len().min(BUF_SIZE)].contains(&0) {
+        return Ok(None);
+    }
+    let mut data = LanguageData { files: 1, ..LanguageData::default() };
+    let mut block = None;
+    for line in String::from_utf8_lossy(&bytes).lines() {
+        match classify_line(line, language, &mut block) {
+            LineKind::Blank => data.blank += 1,
+            LineKind::Comment => data.comment += 1,
+            LineKind::Code => data.code += 1,
+        }
     }
+    Ok(Some(data))
 }
 
-fn count(mut file: impl BufRead, filename: &str) -> MyResult<Data> {
-    let mut lines = 0;
-    let mut words = 0;
-    let mut chars = 0;
-    let mut bytes = 0;
+#[derive(Debug, PartialEq)]
+enum LineKind {
+    Blank,
+    Comment,
+    Code,
+}
 
-    let mut line = String::new();
+// A line with anything outside a comment is code. block holds the index of
+// the block comment left open by an earlier line. Comment markers inside
+// string literals are not recognised.
+fn classify_line(line: &str, language: &Language, block: &mut Option<usize>) -> LineKind {
+    let mut rest = line.trim();
+    if rest.is_empty() {
+        return LineKind::Blank;
+    }
+    let mut code = false;
+    let mut comment = false;
     loop {
-        let b = file.read_line(&mut line)?;
-        if b == 0 {break;}
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(open) = *block {
+            comment = true;
+            let end = &language.block_comment[open].1;
+            match rest.find(end.as_str()) {
+                Some(i) => {
+                    rest = &rest[i + end.len()..];
+                    *block = None;
+                }
+                None => break,
+            }
+            continue;
+        }
+        // the longest marker wins, so Lua's --[[ opens a block rather than
+        // starting a -- line comment
+        let line_marker = language
+            .line_comment
+            .iter()
+            .filter(|marker| rest.starts_with(marker.as_str()))
+            .map(String::len)
+            .max();
+        let opener = language
+            .block_comment
+            .iter()
+            .enumerate()
+            .filter(|(_, (start, _))| rest.starts_with(start.as_str()))
+            .max_by_key(|(_, (start, _))| start.len());
+        match (line_marker, opener) {
+            (line_len, Some((open, (start, _)))) if line_len.is_none_or(|n| n < start.len()) => {
+                comment = true;
+                *block = Some(open);
+                rest = &rest[start.len()..];
+                continue;
+            }
+            (Some(_), _) => {
+                comment = true;
+                break;
+            }
+            _ => {}
+        }
+        // code runs up to the next comment marker, if any
+        code = true;
+        let next = language
+            .line_comment
+            .iter()
+            .chain(language.block_comment.iter().map(|(start, _)| start))
+            .filter_map(|marker| rest.find(marker.as_str()))
+            .min();
+        match next {
+            Some(i) => rest = &rest[i..],
+            None => break,
+        }
+    }
+    if code {
+        LineKind::Code
+    } else if comment {
+        LineKind::Comment
+    } else {
+        LineKind::Blank
+    }
+}
 
-        lines += 1;
-        chars += line.chars().count();
-        words += line.split_whitespace().count();
-        bytes += b;
-        line.clear();
+// Size columns the way GNU wc does: wide enough for the combined size of
+// all regular files, at least 7 when any input is not a regular file, and
+// unpadded for a single count of a single file or a bare total.
+fn number_width(config: &Config) -> usize {
+    let counts = [
+        config.lines,
+        config.words,
+        config.chars,
+        config.bytes,
+        config.max_line_length,
+    ];
+    let single_count = counts.iter().filter(|&&v| v).count() + config.patterns.len() == 1;
+    if config.total == Total::Only || config.streamed_list || (config.files.len() == 1 && single_count) {
+        return 1;
+    }
+    let mut min_width = 1;
+    let mut total_size = 0;
+    for filename in &config.files {
+        let meta = match is_stdin(filename) {
+            true => fs::metadata("/dev/stdin"),
+            false => fs::metadata(filename),
+        };
+        match meta {
+            Ok(meta) if meta.is_file() => total_size += meta.len(),
+            Ok(_) => min_width = 7,
+            Err(_) => {}
+        }
+    }
+    total_size.to_string().len().max(min_width)
+}
+
+fn is_stdin(filename: &Path) -> bool {
+    filename.as_os_str() == "-"
+}
+
+fn open(filename: &Path) -> MyResult<Box<dyn BufRead>> {
+    match is_stdin(filename) {
+        true => Ok(Box::new(BufReader::with_capacity(BUF_SIZE, io::stdin()))),
+        false => Ok(Box::new(BufReader::with_capacity(BUF_SIZE, File::open(filename)?))),
+    }
+}
+
+fn count_file(filename: &Path, options: &CountOptions) -> Result<Data, String> {
+    let name = filename.to_string_lossy();
+    let file = open(filename).map_err(|e| format!("Failed to open {}: {}", name, e))?;
+    count(file, &name, options).map_err(|e| format!("{}: {}", name, e))
+}
+
+// A whole file, or a byte range of a large one
+struct Job {
+    file_num: usize,
+    range: Option<(u64, u64)>,
+}
+
+// Count files on a pool of threads, splitting large regular files into
+// ranges whose counts are merged back. Results come back in file order,
+// exactly as a serial run would produce them.
+fn count_parallel(
+    files: &[PathBuf],
+    options: &CountOptions,
+    jobs: usize,
+) -> Vec<Result<Data, String>> {
+    let mut results: Vec<Option<Result<Data, String>>> = files.iter().map(|_| None).collect();
+    let mut work = Vec::new();
+    for (file_num, filename) in files.iter().enumerate() {
+        // stdin can only be read once, so it stays on this thread
+        if is_stdin(filename) {
+            results[file_num] = Some(count_file(filename, options));
+            continue;
+        }
+        match fs::metadata(filename) {
+            Ok(meta) if meta.is_file() && meta.len() > SPLIT_SIZE => {
+                for start in (0..meta.len()).step_by(SPLIT_SIZE as usize) {
+                    let end = meta.len().min(start + SPLIT_SIZE);
+                    work.push(Job { file_num, range: Some((start, end)) });
+                }
+            }
+            _ => work.push(Job { file_num, range: None }),
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let done: Vec<Mutex<Option<Result<Data, String>>>> =
+        work.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(work.len()) {
+            scope.spawn(|| loop {
+                let job_num = next.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = work.get(job_num) else { break };
+                let filename = &files[job.file_num];
+                let result = match job.range {
+                    None => count_file(filename, options),
+                    Some((start, end)) => count_range(filename, start, end, options),
+                };
+                *done[job_num].lock().unwrap() = Some(result);
+            });
+        }
+    });
+
+    for (job, result) in work.iter().zip(done) {
+        let result = result.into_inner().unwrap().unwrap();
+        let merged = &mut results[job.file_num];
+        match (merged.as_mut(), result) {
+            (None, result) => *merged = Some(result),
+            (Some(Ok(datum)), Ok(part)) => datum.add(&part),
+            (Some(Ok(_)), Err(e)) => *merged = Some(Err(e)),
+            (Some(Err(_)), _) => {}
+        }
+    }
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+// Count the lines that start inside [start, end). Every range then begins
+// just after a newline, where no word or character can be in progress, so
+// the counts of all ranges add up to those of the whole file.
+fn count_range(
+    filename: &Path,
+    start: u64,
+    end: u64,
+    options: &CountOptions,
+) -> Result<Data, String> {
+    let name = filename.to_string_lossy();
+    let range = || -> MyResult<Data> {
+        let mut file = File::open(filename)?;
+        let from = line_start(&mut file, start)?;
+        let to = line_start(&mut file, end)?;
+        file.seek(SeekFrom::Start(from))?;
+        let reader = BufReader::with_capacity(BUF_SIZE, file.take(to.saturating_sub(from)));
+        count(reader, &name, options)
+    };
+    range().map_err(|e| format!("{}: {}", name, e))
+}
+
+// The offset just past the first newline at or after pos - 1
+fn line_start(file: &mut File, pos: u64) -> MyResult<u64> {
+    if pos == 0 {
+        return Ok(0);
+    }
+    file.seek(SeekFrom::Start(pos - 1))?;
+    let mut reader = BufReader::with_capacity(BUF_SIZE, file);
+    let mut offset = pos - 1;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(offset);
+        }
+        if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+            return Ok(offset + i as u64 + 1);
+        }
+        let len = buf.len();
+        offset += len as u64;
+        reader.consume(len);
+    }
+}
+
+// Lines and bytes alone never need the input decoded
+fn count(mut file: impl BufRead, filename: &str, options: &CountOptions) -> MyResult<Data> {
+    let mut counter = Counter { options: options.clone(), ..Counter::default() };
+    loop {
+        let chunk = file.fill_buf()?;
+        if chunk.is_empty() { break; }
+        counter.update(chunk);
+        let len = chunk.len();
+        file.consume(len);
+    }
+    Ok(counter.finish(filename))
+}
+
+// Counts a stream fed in arbitrary chunks. Words and characters follow
+// GNU wc in a UTF-8 locale: invalid bytes are not characters and neither
+// start nor end a word, and a multibyte sequence may straddle two chunks.
+#[derive(Debug, Default)]
+struct Counter {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+    chars: usize,
+    options: CountOptions,
+    in_word: bool,
+    line_pos: usize,
+    max_line_length: usize,
+    pending: Vec<u8>,
+    // the unfinished last line, for word modes and patterns that work a
+    // line at a time
+    line: Vec<u8>,
+    line_counts: LineCounts,
+    stats: LineStats,
+}
+
+impl Counter {
+    fn update(&mut self, chunk: &[u8]) {
+        self.bytes += chunk.len();
+        self.lines += bytecount::count(chunk, b'\n');
+        if self.options.stats {
+            self.stats.update(chunk);
+        }
+        if !matches!(self.options.word_mode, WordMode::Gnu) || !self.options.patterns.is_empty() {
+            self.add_lines(chunk);
+        }
+        if !self.options.decode { return; }
+
+        let mut i = 0;
+        if !self.pending.is_empty() {
+            let take = chunk.len().min(MAX_UTF8_LEN - self.pending.len());
+            let mut buf = self.pending.clone();
+            buf.extend_from_slice(&chunk[..take]);
+            match decode_utf8(&buf) {
+                Utf8::Incomplete => {
+                    self.pending = buf;
+                    return;
+                }
+                Utf8::Char(c, len) => {
+                    self.add_char(c);
+                    i = len - self.pending.len();
+                }
+                // the held bytes are all continuation bytes after the lead,
+                // so none of them can start a character either
+                Utf8::Invalid => {}
+            }
+            self.pending.clear();
+        }
+
+        while i < chunk.len() {
+            let byte = chunk[i];
+            if byte < 0x80 {
+                // ASCII needs neither decoding nor a table lookup
+                self.chars += 1;
+                self.add_ascii(byte);
+                i += 1;
+                continue;
+            }
+            match decode_utf8(&chunk[i..]) {
+                Utf8::Char(c, len) => {
+                    self.add_char(c);
+                    i += len;
+                }
+                Utf8::Invalid => i += 1,
+                Utf8::Incomplete => {
+                    self.pending.extend_from_slice(&chunk[i..]);
+                    break;
+                }
+            }
+        }
+    }
+
+    // No word or pattern match spans a newline, so these can be counted
+    // line by line
+    fn add_lines(&mut self, chunk: &[u8]) {
+        let mut rest = chunk;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            if self.line.is_empty() {
+                self.line_counts.add(&self.options, &rest[..i]);
+            } else {
+                self.line.extend_from_slice(&rest[..i]);
+                self.line_counts.add(&self.options, &self.line);
+                self.line.clear();
+            }
+            rest = &rest[i + 1..];
+        }
+        self.line.extend_from_slice(rest);
+    }
+
+    // Line widths follow GNU wc -L: tabs stop every 8 columns and \r and
+    // \f end a line just like \n does
+    fn add_ascii(&mut self, byte: u8) {
+        match byte {
+            b'\n' | b'\r' | b'\x0c' => {
+                self.max_line_length = self.max_line_length.max(self.line_pos);
+                self.line_pos = 0;
+                self.in_word = false;
+            }
+            b'\t' => {
+                self.line_pos += 8 - self.line_pos % 8;
+                self.in_word = false;
+            }
+            b' ' => {
+                self.line_pos += 1;
+                self.in_word = false;
+            }
+            b'\x0b' => self.in_word = false,
+            b'!'..=b'~' => {
+                self.line_pos += 1;
+                if !self.in_word {
+                    self.words += 1;
+                    self.in_word = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_char(&mut self, c: u32) {
+        self.chars += 1;
+        if c < 0x80 {
+            self.add_ascii(c as u8);
+            return;
+        }
+        if !is_printable(c) {
+            return;
+        }
+        // wide characters take two columns and combining marks none; like
+        // wcwidth, a soft hyphen is visible
+        self.line_pos += match c {
+            0xad => 1,
+            _ => char::from_u32(c).and_then(|c| c.width()).unwrap_or(0),
+        };
+        if is_space(c) {
+            self.in_word = false;
+        } else if !self.in_word {
+            self.words += 1;
+            self.in_word = true;
+        }
+    }
+
+    // an incomplete sequence at the end of input is not a character
+    fn finish(mut self, filename: &str) -> Data {
+        if !self.line.is_empty() {
+            self.line_counts.add(&self.options, &self.line);
+        }
+        let words = match self.options.word_mode {
+            WordMode::Gnu => self.words,
+            _ => self.line_counts.words,
+        };
+        let mut patterns = self.line_counts.patterns;
+        patterns.resize(self.options.patterns.len(), 0);
+        Data {
+            filename: filename.to_string(),
+            lines: self.lines,
+            words,
+            bytes: self.bytes,
+            chars: self.chars,
+            max_line_length: self.max_line_length.max(self.line_pos),
+            patterns,
+            stats: self.options.stats.then(|| {
+                self.stats.finish();
+                self.stats
+            }),
+        }
+    }
+}
+
+// Words and pattern counts of complete lines
+#[derive(Debug, Default)]
+struct LineCounts {
+    words: usize,
+    patterns: Vec<usize>,
+}
+
+impl LineCounts {
+    fn add(&mut self, options: &CountOptions, line: &[u8]) {
+        self.words += count_words(&options.word_mode, line);
+        self.patterns.resize(options.patterns.len(), 0);
+        for (count, re) in self.patterns.iter_mut().zip(&options.patterns) {
+            *count += match options.pattern_mode {
+                PatternMode::Matches => re.find_iter(line).filter(|m| !m.is_empty()).count(),
+                PatternMode::Lines => re.find_iter(line).any(|m| !m.is_empty()) as usize,
+            };
+        }
+    }
+}
+
+fn count_words(word_mode: &WordMode, line: &[u8]) -> usize {
+    match word_mode {
+        WordMode::Gnu => 0,
+        WordMode::Unicode => String::from_utf8_lossy(line).unicode_words().count(),
+        WordMode::Regex(re) => re.find_iter(line).filter(|m| !m.is_empty()).count(),
     }
-    Ok(Data {
-        filename: filename.to_string(),
-        lines,
-        words,
-        chars,
-        bytes,
-    })
 }
 
-fn print_data(config: &Config, data: &Data) -> MyResult<String> {
-    let mut output = String::new();
-    if config.lines {
-        output.push_str(&format!("{:8}", data.lines));
+#[derive(Debug, PartialEq)]
+enum Utf8 {
+    Char(u32, usize),
+    Invalid,
+    Incomplete,
+}
+
+// Decode one character the way glibc does: the original UTF-8 of up to six
+// bytes, rejecting overlong forms and surrogates
+fn decode_utf8(bytes: &[u8]) -> Utf8 {
+    let lead = bytes[0] as u32;
+    let (len, mut value, min) = match lead {
+        0x00..=0x7f => return Utf8::Char(lead, 1),
+        0xc0..=0xdf => (2, lead & 0x1f, 0x80),
+        0xe0..=0xef => (3, lead & 0x0f, 0x800),
+        0xf0..=0xf7 => (4, lead & 0x07, 0x1_0000),
+        0xf8..=0xfb => (5, lead & 0x03, 0x20_0000),
+        0xfc..=0xfd => (6, lead & 0x01, 0x400_0000),
+        _ => return Utf8::Invalid,
+    };
+    for i in 1..len {
+        match bytes.get(i) {
+            None => return Utf8::Incomplete,
+            Some(&b) if b & 0xc0 == 0x80 => value = (value << 6) | (b & 0x3f) as u32,
+            Some(_) => return Utf8::Invalid,
+        }
     }
-    if config.words {
-        output.push_str(&format!("{:8}", data.words));
+    if value < min || (0xd800..=0xdfff).contains(&value) {
+        Utf8::Invalid
+    } else {
+        Utf8::Char(value, len)
     }
-    if config.chars {
-        output.push_str(&format!("{:8}", data.chars));
+}
+
+// iswspace plus the no-break spaces GNU wc also treats as separators
+fn is_space(c: u32) -> bool {
+    matches!(c,
+        0x09..=0x0d | 0x20 | 0xa0 | 0x1680 | 0x2000..=0x200a
+        | 0x202f | 0x205f | 0x2060 | 0x3000)
+}
+
+// Like iswprint: control, unassigned and line/paragraph separator code
+// points (and values beyond Unicode) do not start words
+fn is_printable(c: u32) -> bool {
+    if c < 0x80 {
+        return (0x20..0x7f).contains(&c);
     }
-    if config.bytes {
-        output.push_str(&format!("{:8}", data.bytes));
+    match char::from_u32(c) {
+        None => false,
+        Some(c) => !matches!(
+            get_general_category(c),
+            GeneralCategory::Control
+                | GeneralCategory::Unassigned
+                | GeneralCategory::LineSeparator
+                | GeneralCategory::ParagraphSeparator
+        ),
     }
-    if data.filename != "-" {
+}
+
+fn print_data(config: &Config, data: &Data, width: usize) -> MyResult<String> {
+    let counts = [
+        (config.lines, data.lines),
+        (config.words, data.words),
+        (config.chars, data.chars),
+        (config.bytes, data.bytes),
+        (config.max_line_length, data.max_line_length),
+    ];
+    let mut output = counts
+        .iter()
+        .filter(|(show, _)| *show)
+        .map(|(_, value)| value)
+        .chain(&data.patterns)
+        .map(|value| format!("{:>1$}", value, width))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !data.filename.is_empty() {
         output.push_str(&format!(" {}", data.filename));
     }
     Ok(output)
-}
\ No newline at end of file
+}
+
+// The --stats report that follows a row of text output
+fn format_stats(stats: &StatsSummary) -> String {
+    let mut out = format!(
+        "  lines: {}, empty: {}, trailing whitespace: {}, CR endings: {}\n",
+        stats.lines, stats.empty, stats.trailing_whitespace, stats.cr_endings
+    );
+    if stats.missing_final_newline {
+        out.push_str("  missing final newline\n");
+    }
+    if let (Some(min), Some(max), Some(mean), Some(median), Some(p90), Some(p99)) =
+        (stats.min, stats.max, stats.mean, stats.median, stats.p90, stats.p99)
+    {
+        out.push_str(&format!(
+            "  length: min {} max {} mean {:.2} median {} p90 {} p99 {}\n",
+            min, max, mean, median, p90, p99
+        ));
+    }
+    let most = stats.histogram.iter().map(|b| b.lines).max().unwrap_or(0);
+    let label_width = stats.histogram.last().map_or(0, |b| b.to.to_string().len());
+    let count_width = most.to_string().len();
+    for bucket in &stats.histogram {
+        let range = match bucket.from == bucket.to {
+            true => bucket.from.to_string(),
+            false => format!("{}-{}", bucket.from, bucket.to),
+        };
+        // any line at all gets at least one mark
+        let bar = (bucket.lines * HISTOGRAM_WIDTH).div_ceil(most.max(1));
+        let line = format!(
+            "  {:>w$} {:>c$} {}",
+            range,
+            bucket.lines,
+            "#".repeat(bar),
+            w = 2 * label_width + 1,
+            c = count_width
+        );
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    if !stats.common_bytes.is_empty() {
+        let bytes = stats
+            .common_bytes
+            .iter()
+            .map(|b| format!("'{}' {}", b.byte.escape_ascii(), b.count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.push_str(&format!("  common bytes: {}\n", bytes));
+    }
+    out
+}
+
+fn print_records(config: &Config, files: &[Data], total: Option<&Data>) -> MyResult<()> {
+    let rows = files.iter().map(|data| (data, false)).chain(total.map(|data| (data, true)));
+    let records: Vec<Record> = rows
+        .map(|(data, total)| Record {
+            filename: &data.filename,
+            total,
+            lines: data.lines,
+            words: data.words,
+            bytes: data.bytes,
+            chars: data.chars,
+            max_line_length: config.max_line_length.then_some(data.max_line_length),
+            patterns: config
+                .patterns
+                .iter()
+                .zip(&data.patterns)
+                .map(|(pattern, &count)| PatternCount { pattern: pattern.as_str(), count })
+                .collect(),
+            stats: data.stats.as_ref().map(LineStats::summary),
+        })
+        .collect();
+    let mut header = vec!["filename", "total", "lines", "words", "bytes", "chars"];
+    if config.max_line_length {
+        header.push("max_line_length");
+    }
+    // patterns are numbered in the order given, as a pattern itself could
+    // clash with another column; JSON carries the patterns themselves
+    let pattern_columns: Vec<String> =
+        (1..=config.patterns.len()).map(|i| format!("pattern_{}", i)).collect();
+    header.extend(pattern_columns.iter().map(String::as_str));
+    // the histogram and byte counts do not fit in a table
+    if config.stats {
+        header.extend([
+            "empty",
+            "trailing_whitespace",
+            "cr_endings",
+            "missing_final_newline",
+            "min_length",
+            "max_length",
+            "mean_length",
+            "median_length",
+            "p90_length",
+            "p99_length",
+        ]);
+    }
+    print_structured(config.format, &header, &records, |record| {
+        let mut row = vec![
+            record.filename.to_string(),
+            record.total.to_string(),
+            record.lines.to_string(),
+            record.words.to_string(),
+            record.bytes.to_string(),
+            record.chars.to_string(),
+        ];
+        row.extend(record.max_line_length.map(|n| n.to_string()));
+        row.extend(record.patterns.iter().map(|p| p.count.to_string()));
+        if let Some(stats) = &record.stats {
+            let optional = |value: Option<String>| value.unwrap_or_default();
+            row.extend([
+                stats.empty.to_string(),
+                stats.trailing_whitespace.to_string(),
+                stats.cr_endings.to_string(),
+                stats.missing_final_newline.to_string(),
+                optional(stats.min.map(|n| n.to_string())),
+                optional(stats.max.map(|n| n.to_string())),
+                optional(stats.mean.map(|n| format!("{:.2}", n))),
+                optional(stats.median.map(|n| n.to_string())),
+                optional(stats.p90.map(|n| n.to_string())),
+                optional(stats.p99.map(|n| n.to_string())),
+            ]);
+        }
+        row
+    })
+}
+
+// JSON goes through serde; CSV and TSV rows come from to_row so that
+// optional fields can be left out of the header as well
+fn print_structured<T: Serialize>(
+    format: Format,
+    header: &[&str],
+    records: &[T],
+    to_row: impl Fn(&T) -> Vec<String>,
+) -> MyResult<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, records)?;
+            writeln!(out)?;
+        }
+        Format::Jsonl => {
+            for record in records {
+                serde_json::to_writer(&mut out, record)?;
+                writeln!(out)?;
+            }
+        }
+        _ => {
+            let mut writer = WriterBuilder::new()
+                .delimiter(if format == Format::Tsv { b'\t' } else { b',' })
+                .has_headers(false)
+                .from_writer(out);
+            writer.write_record(header)?;
+            for record in records {
+                writer.write_record(to_row(record))?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        classify_line, count, count_file, count_range, decode_utf8, load_languages, Bucket,
+        CountOptions, Counter, Data, LineKind, LineStats, PatternMode, Utf8, WordMode,
+    };
+    use regex::bytes::Regex;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    fn count_str(text: &[u8]) -> Data {
+        let options = CountOptions { decode: true, ..CountOptions::default() };
+        count(Cursor::new(text), "-", &options).unwrap()
+    }
+
+    #[test]
+    fn test_count() {
+        let text = b"I don't want the world. I just want your half.\r\n";
+        let data = count_str(text);
+        assert_eq!(data.lines, 1);
+        assert_eq!(data.words, 10);
+        assert_eq!(data.chars, 48);
+        assert_eq!(data.bytes, 48);
+
+        // no final newline, so no line
+        let data = count_str(b"one\ntwo");
+        assert_eq!((data.lines, data.words), (1, 2));
+
+        let data = count(Cursor::new(text), "-", &CountOptions::default()).unwrap();
+        assert_eq!((data.lines, data.words, data.bytes), (1, 0, 48));
+    }
+
+    #[test]
+    fn test_count_like_gnu() {
+        // expected values from GNU wc 9.1 in C.UTF-8
+        let data = count_str(b"a\xffb c\xe9 \x01 d\n\xc3\xa9t\xc3\xa9 \xe2\x80\x83x\xc2\xa0y");
+        assert_eq!(
+            (data.lines, data.words, data.chars, data.bytes),
+            (1, 6, 17, 24)
+        );
+        let data = count_str(b"\xe3\x80\x80a\x0bb\x0cc\rd");
+        assert_eq!((data.lines, data.words, data.chars), (0, 4, 8));
+        let data = count_str(b"x \xf4\x8f\xbf\xbf y \xed\xa0\x80");
+        assert_eq!((data.words, data.chars), (2, 6));
+        let data = count_str("\u{200b} \u{378} \u{fdd0} \u{e000}".as_bytes());
+        assert_eq!((data.words, data.chars), (2, 7));
+    }
+
+    #[test]
+    fn test_max_line_length() {
+        // tabs stop every 8 columns, CJK is double width, combining is zero
+        let data = count_str("ab\tc\n\u{65e5}\u{672c}e\u{301}\r\nlast".as_bytes());
+        assert_eq!(data.max_line_length, 9);
+        let data = count_str("\u{65e5}\u{672c}\u{8a9e}\u{306e}\u{884c}\n".as_bytes());
+        assert_eq!(data.max_line_length, 10);
+        let data = count_str(b"abc\rx\x0cy\x01\xff");
+        assert_eq!(data.max_line_length, 3);
+    }
+
+    #[test]
+    fn test_count_across_chunks() {
+        let text = ["h\u{e9}llo w\u{f6}rld \u{1f600}\u{3000}end\n".as_bytes(), b"\xff"].concat();
+        let whole = count_str(&text);
+        // every split point, including inside multibyte sequences
+        for size in 1..text.len() {
+            let options = CountOptions { decode: true, ..CountOptions::default() };
+            let mut counter = Counter { options, ..Counter::default() };
+            text.chunks(size).for_each(|chunk| counter.update(chunk));
+            let data = counter.finish("-");
+            assert_eq!(
+                (data.lines, data.words, data.chars, data.bytes, data.max_line_length),
+                (whole.lines, whole.words, whole.chars, whole.bytes, whole.max_line_length),
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_count_range() {
+        let filename = Path::new("tests/inputs/mixed.txt");
+        let options = CountOptions { decode: true, ..CountOptions::default() };
+        let whole = count_file(filename, &options).unwrap();
+        let len = whole.bytes as u64;
+        // any way of cutting the file gives the same totals
+        for first in 0..len {
+            for second in first..=len {
+                let mut sum = count_range(filename, 0, first, &options).unwrap();
+                sum.add(&count_range(filename, first, second, &options).unwrap());
+                sum.add(&count_range(filename, second, len, &options).unwrap());
+                assert_eq!(
+                    (sum.lines, sum.words, sum.chars, sum.bytes, sum.max_line_length),
+                    (whole.lines, whole.words, whole.chars, whole.bytes, whole.max_line_length),
+                    "ranges split at {} and {}",
+                    first,
+                    second
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_word_modes() {
+        let text = "Hello, world -- \u{3053}\u{3093}\u{306b}\u{3061}\u{306f}\u{4e16}\u{754c} 3.14\nfoo_bar(x)";
+        let words = |word_mode, chunk_size| {
+            let options = CountOptions { decode: true, word_mode, ..CountOptions::default() };
+            let mut counter = Counter { options, ..Counter::default() };
+            text.as_bytes().chunks(chunk_size).for_each(|chunk| counter.update(chunk));
+            counter.finish("-").words
+        };
+        assert_eq!(words(WordMode::Gnu, 1), 6);
+        let identifiers = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+        for size in [1, 5, text.len()] {
+            // Hello, world, 7 kana/kanji, 3.14, foo_bar, x
+            assert_eq!(words(WordMode::Unicode, size), 12);
+            assert_eq!(words(WordMode::Regex(identifiers.clone()), size), 4);
+        }
+        // empty matches are not words
+        let maybe = Regex::new(r"o*").unwrap();
+        assert_eq!(words(WordMode::Regex(maybe), 3), 3);
+    }
+
+    #[test]
+    fn test_decode_utf8() {
+        assert_eq!(decode_utf8(b"\xc3\xa9"), Utf8::Char(0xe9, 2));
+        assert_eq!(decode_utf8(b"\xf0\x9f\x98\x80"), Utf8::Char(0x1f600, 4));
+        assert_eq!(decode_utf8(b"\xf0\x9f"), Utf8::Incomplete);
+        assert_eq!(decode_utf8(b"\xc0\x80"), Utf8::Invalid);
+        assert_eq!(decode_utf8(b"\xed\xa0\x80"), Utf8::Invalid);
+        assert_eq!(decode_utf8(b"\xe2\x80a"), Utf8::Invalid);
+        assert_eq!(decode_utf8(b"\x80"), Utf8::Invalid);
+    }
+
+    #[test]
+    fn test_classify_line() {
+        let languages = load_languages(None).unwrap();
+        let rust = languages.iter().find(|l| l.name == "Rust").unwrap();
+        let mut block = None;
+        let mut kinds = |line| classify_line(line, rust, &mut block);
+        assert_eq!(kinds("   "), LineKind::Blank);
+        assert_eq!(kinds("// note"), LineKind::Comment);
+        assert_eq!(kinds("let x = 1; // note"), LineKind::Code);
+        assert_eq!(kinds("/* open"), LineKind::Comment);
+        assert_eq!(kinds(""), LineKind::Blank);
+        assert_eq!(kinds("still */ let y = 2;"), LineKind::Code);
+        assert_eq!(kinds("/* a */ /* b */"), LineKind::Comment);
+
+        let python = languages.iter().find(|l| l.name == "Python").unwrap();
+        let mut block = None;
+        assert_eq!(classify_line("\"\"\"doc", python, &mut block), LineKind::Comment);
+        assert_eq!(classify_line("x = 1", python, &mut block), LineKind::Comment);
+        assert_eq!(classify_line("end\"\"\"", python, &mut block), LineKind::Comment);
+        assert_eq!(classify_line("x = 1", python, &mut block), LineKind::Code);
+
+        let lua = languages.iter().find(|l| l.name == "Lua").unwrap();
+        let mut block = None;
+        let kinds: Vec<_> = ["--[[", "local x = 1", "print(x)", "]]", "print(2) -- done"]
+            .iter()
+            .map(|line| classify_line(line, lua, &mut block))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                LineKind::Comment,
+                LineKind::Comment,
+                LineKind::Comment,
+                LineKind::Comment,
+                LineKind::Code
+            ]
+        );
+        assert_eq!(classify_line("-- note", lua, &mut block), LineKind::Comment);
+    }
+
+    #[test]
+    fn test_line_stats() {
+        let text = b"a\n\nabc  \r\nabcdefgh\r\nlonger line here\tx";
+        for size in [1, 2, 7, text.len()] {
+            let mut stats = LineStats::default();
+            text.chunks(size).for_each(|chunk| stats.update(chunk));
+            stats.finish();
+            let summary = stats.summary();
+            assert_eq!(summary.lines, 5);
+            assert_eq!(summary.empty, 1);
+            assert_eq!(summary.trailing_whitespace, 1);
+            assert_eq!(summary.cr_endings, 2);
+            assert!(summary.missing_final_newline);
+            assert_eq!((summary.min, summary.max), (Some(0), Some(18)));
+            assert_eq!(summary.median, Some(5.0));
+            assert_eq!((summary.p90, summary.p99), (Some(18), Some(18)));
+            assert_eq!(
+                summary.histogram[2..4],
+                [Bucket { from: 2, to: 3, lines: 0 }, Bucket { from: 4, to: 7, lines: 1 }]
+            );
+            assert_eq!(summary.common_bytes[0].byte, b'e');
+        }
+
+        let mut stats = LineStats::default();
+        stats.update(b"1\n22\n");
+        stats.finish();
+        let summary = stats.summary();
+        assert!(!summary.missing_final_newline);
+        assert_eq!((summary.mean, summary.median), (Some(1.5), Some(1.5)));
+        assert_eq!(LineStats::default().summary().min, None);
+    }
+
+    #[test]
+    fn test_count_patterns() {
+        let text = b"ERROR x ERROR\nWARN y\nok\nERRORS";
+        let patterns = vec![Regex::new("ERROR").unwrap(), Regex::new("W?A?").unwrap()];
+        // empty matches count in neither mode
+        let modes = [(PatternMode::Matches, [3, 1]), (PatternMode::Lines, [2, 1])];
+        for (pattern_mode, expected) in modes {
+            let options = CountOptions {
+                patterns: patterns.clone(),
+                pattern_mode,
+                ..CountOptions::default()
+            };
+            for size in [1, 4, text.len()] {
+                let mut counter = Counter { options: options.clone(), ..Counter::default() };
+                text.chunks(size).for_each(|chunk| counter.update(chunk));
+                assert_eq!(counter.finish("-").patterns, expected);
+            }
+        }
+    }
+}
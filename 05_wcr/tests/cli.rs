@@ -1,3 +1,6 @@
+// the original tests pass argument arrays by reference
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
@@ -9,6 +12,15 @@ const PRG: &str = "wcr";
 const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const ATLAMAL: &str = "tests/inputs/atlamal.txt";
+const MIXED: &str = "tests/inputs/mixed.txt";
+const WIDTHS: &str = "tests/inputs/widths.txt";
+const MULTILINGUAL: &str = "tests/inputs/multilingual.txt";
+const TREE: &str = "tests/inputs/tree";
+const LANG_CONFIG: &str = "tests/inputs/lang.toml";
+const STATS: &str = "tests/inputs/stats.txt";
+const LOG: &str = "tests/inputs/log.txt";
+const FILES0: &str = "tests/inputs/files0.txt";
+const FILES: &str = "tests/inputs/files.txt";
 
 // --------------------------------------------------
 fn gen_bad_file() -> String {
@@ -212,3 +224,290 @@ fn test_all_words_lines() -> TestResult {
 fn test_all_bytes_lines() -> TestResult {
     run(&["-cl", EMPTY, FOX, ATLAMAL], "tests/expected/all.cl.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn mixed_invalid_utf8() -> TestResult {
+    run(&[MIXED], "tests/expected/mixed.txt.out")?;
+    run(&["-lwm", MIXED], "tests/expected/mixed.txt.lwm.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn test_all_jobs() -> TestResult {
+    run(&["-j", "4", EMPTY, FOX, ATLAMAL], "tests/expected/all.out")?;
+    run(&["--jobs", "0", "-lwm", EMPTY, FOX, ATLAMAL], "tests/expected/all.lwm.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_jobs() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-j", "many", FOX])
+        .assert()
+        .failure()
+        .stderr("invalid number of jobs -- many\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn total_modes() -> TestResult {
+    run(&["--total=never", EMPTY, FOX, ATLAMAL], "tests/expected/all.total-never.out")?;
+    run(&["--total=only", EMPTY, FOX, ATLAMAL], "tests/expected/all.total-only.out")?;
+    run(&["--total=always", "-l", FOX], "tests/expected/fox.txt.l.total-always.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn named_stdin() -> TestResult {
+    let input = fs::read_to_string(FOX)?;
+    Command::cargo_bin(PRG)?
+        .args(["-", FOX])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "      1       9      48 -\n      1       9      48 tests/inputs/fox.txt\n      2      18      96 total\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_line_length() -> TestResult {
+    run(&["-lL", WIDTHS], "tests/expected/widths.txt.lL.out")?;
+    // the total is the longest line of any file
+    run(
+        &["--max-line-length", FOX, ATLAMAL, WIDTHS],
+        "tests/expected/all-widths.L.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn structured_formats() -> TestResult {
+    run(&["--format", "json", EMPTY, FOX, ATLAMAL], "tests/expected/all.json.out")?;
+    run(&["--format", "jsonl", "-L", FOX], "tests/expected/fox.txt.L.jsonl.out")?;
+    run(&["--format", "csv", EMPTY, FOX, ATLAMAL], "tests/expected/all.csv.out")?;
+    run(
+        &["--format", "tsv", "--total", "never", EMPTY, FOX],
+        "tests/expected/all.total-never.tsv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_escapes_filenames() -> TestResult {
+    let dir = std::env::temp_dir().join(format!("wcr-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let odd = dir.join("odd \"name\", too.txt");
+    fs::copy(FOX, &odd)?;
+    let odd = odd.to_string_lossy().to_string();
+    let output = Command::cargo_bin(PRG)?
+        .args(["--format", "csv", &odd])
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!(
+            "filename,total,lines,words,bytes,chars\n\"{}\",false,1,9,48,48\n",
+            odd.replace('"', "\"\"")
+        )
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn word_modes() -> TestResult {
+    run(
+        &["--words=unicode", MULTILINGUAL],
+        "tests/expected/multilingual.txt.unicode.out",
+    )?;
+    run(
+        &["--word-regex", "[A-Za-z_][A-Za-z0-9_]*", MULTILINGUAL],
+        "tests/expected/multilingual.txt.regex.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_word_regex() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--word-regex", "(", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid --word-regex \"(\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive() -> TestResult {
+    run(&["-r", TREE], "tests/expected/tree.recursive.out")?;
+    run(
+        &["--recursive", "--format", "csv", "--lang-config", LANG_CONFIG, TREE],
+        "tests/expected/tree.recursive.lang.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_lang_config() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--lang-config", FOX, TREE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(format!("{}: ", FOX)));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats() -> TestResult {
+    run(&["--stats", STATS, FOX], "tests/expected/stats.txt.fox.txt.stats.out")?;
+    run(&["--stats", "-j", "2", STATS, FOX], "tests/expected/stats.txt.fox.txt.stats.out")?;
+    run(
+        &["--stats", "--format", "csv", EMPTY, STATS],
+        "tests/expected/stats.txt.stats.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn count_patterns() -> TestResult {
+    run(
+        &["--count-pattern", "ERROR", "--count-pattern", "WARN", LOG, FOX],
+        "tests/expected/log.txt.fox.txt.patterns.out",
+    )?;
+    run(
+        &["-l", "--count-pattern", "ERROR", "--pattern-mode", "lines", "-j", "2", LOG],
+        "tests/expected/log.txt.patterns.lines.out",
+    )?;
+    run(
+        &["--count-pattern", "ERROR", "--count-pattern", "W[A-Z]+", "--format", "csv", LOG],
+        "tests/expected/log.txt.patterns.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_count_pattern() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--count-pattern", "(", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid --count-pattern \"(\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from() -> TestResult {
+    run(&["--files0-from", FILES0], "tests/expected/all.out")?;
+    run(&["--files-from", FILES], "tests/expected/all.out")?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files0_from_pipe_unpadded() -> TestResult {
+    // like GNU wc, a piped list gives nothing to size the columns by
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from=-")
+        .write_stdin(fs::read(FILES0)?)
+        .assert()
+        .success()
+        .stdout(
+            "0 0 0 tests/inputs/empty.txt\n\
+             1 9 48 tests/inputs/fox.txt\n\
+             4 29 177 tests/inputs/atlamal.txt\n\
+             5 38 225 total\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_files0_from() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--files0-from", FILES0, FOX])
+        .assert()
+        .failure()
+        .stderr("file operands cannot be combined with --files0-from\n");
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from=-")
+        .write_stdin("a\0\0b")
+        .assert()
+        .failure()
+        .stderr("-:2: invalid zero-length file name\n");
+    Command::cargo_bin(PRG)?
+        .arg("--files0-from=-")
+        .write_stdin("-\0")
+        .assert()
+        .failure()
+        .stderr("when reading file names from stdin, no file name of '-' allowed\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_recursive_with_counts() -> TestResult {
+    for flag in &["-l", "-w", "-c", "-m", "-L", "--words=unicode", "-j2"] {
+        Command::cargo_bin(PRG)?
+            .args(["-r", flag, TREE])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("cannot be used with '--recursive'"));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn structured_total_row() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--format", "jsonl", "--total", "only", FOX])
+        .assert()
+        .success()
+        .stdout(
+            "{\"filename\":\"\",\"total\":true,\"lines\":1,\"words\":9,\"bytes\":48,\"chars\":48}\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn files0_from_non_utf8_name() -> TestResult {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = std::env::temp_dir().join(format!("wcr-files0-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let name = dir.join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt"));
+    fs::copy(FOX, &name)?;
+    let mut list = name.as_os_str().as_bytes().to_vec();
+    list.push(0);
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files0-from=-", "--total=only"])
+        .write_stdin(list)
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr)?, "");
+    assert_eq!(String::from_utf8(output.stdout)?, "1 9 48\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_empty_comment_marker() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--lang-config", "tests/inputs/empty-marker.toml", TREE])
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .failure()
+        .stderr("tests/inputs/empty-marker.toml: empty comment marker for Rust\n");
+    Ok(())
+}
@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+"""
+Build the thing.
+"""
+
+import sys  # for argv
+print(sys.argv)
@@ -0,0 +1,7 @@
+// entry point
+fn main() {
+    /* greet
+       the world */
+    println!("hello"); // trailing
+
+}
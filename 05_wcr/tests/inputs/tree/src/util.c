@@ -0,0 +1,4 @@
+#include <stdio.h>
+
+/* one-line block */
+int twice(int x) { return 2 * x; }
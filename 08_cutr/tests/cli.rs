@@ -1,3 +1,6 @@
+// the original tests pass argument arrays by reference
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
@@ -337,3 +340,12 @@ fn tsv_c1_8() -> TestResult {
 fn repeated_value() -> TestResult {
     run(&[BOOKS, "-c", "1,1"], "tests/expected/books.c1,1.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn csv_quoted_delimiter() -> TestResult {
+    run(
+        &["tests/inputs/movies2.csv", "-f", "1,3", "-d", ","],
+        "tests/expected/movies2.csv.f1,3.dcomma.out",
+    )
+}
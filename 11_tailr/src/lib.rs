@@ -0,0 +1,606 @@
+use crate::TakeValue::*;
+use clap::{App, Arg};
+use std::cmp;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+type MyResult<T> = Result<T, Box<dyn Error>>;
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+// A line or byte count: "+N" starts from the Nth line or byte, while
+// "N" or "-N" takes the last N
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum TakeValue {
+    PlusZero,
+    TakeNum(i64),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Follow {
+    Descriptor,
+    Name,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    files: Vec<String>,
+    lines: TakeValue,
+    bytes: Option<TakeValue>,
+    quiet: bool,
+    follow: Option<Follow>,
+    sleep_interval: Duration,
+}
+
+pub fn get_args() -> MyResult<Config> {
+    let matches = App::new("tailr")
+    .version("0.1.0")
+    .author("Chandler Wilkerson <chwilk@gmail.com>")
+    .about("Rust tail")
+    .arg(
+        Arg::with_name("files")
+        .value_name("FILES")
+        .help("Files or - for STDIN")
+        .default_value("-")
+        .min_values(0),
+    )
+    .arg(
+        Arg::with_name("lines")
+        .value_name("LINES")
+        .short("n")
+        .long("lines")
+        .help("output the last NUM lines, default 10; \
+               or use +NUM to output starting with line NUM")
+        .default_value("10")
+        .takes_value(true)
+        .allow_hyphen_values(true),
+    )
+    .arg(
+        Arg::with_name("bytes")
+        .value_name("BYTES")
+        .short("c")
+        .long("bytes")
+        .help("output the last NUM bytes; \
+               or use +NUM to output starting with byte NUM")
+        .takes_value(true)
+        .allow_hyphen_values(true)
+        .conflicts_with("lines"),
+    )
+    .arg(
+        Arg::with_name("quiet")
+        .short("q")
+        .long("quiet")
+        .help("never output headers giving file names")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("follow")
+        .short("f")
+        .long("follow")
+        .help("output appended data as the file grows")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("follow_name")
+        .short("F")
+        .help("like -f, but follow the file name across rotation \
+               and keep trying if it is missing")
+        .takes_value(false),
+    )
+    .arg(
+        Arg::with_name("sleep_interval")
+        .value_name("SECONDS")
+        .short("s")
+        .long("sleep-interval")
+        .help("with -f or -F, check files at least every SECONDS seconds")
+        .default_value("1.0")
+        .takes_value(true),
+    ).get_matches();
+
+    let lines = matches
+        .value_of("lines")
+        .map(parse_num)
+        .transpose()
+        .map_err(|e| format!("illegal line count -- {}", e))?;
+
+    let bytes = matches
+        .value_of("bytes")
+        .map(parse_num)
+        .transpose()
+        .map_err(|e| format!("illegal byte count -- {}", e))?;
+
+    let follow = if matches.is_present("follow_name") {
+        Some(Follow::Name)
+    } else if matches.is_present("follow") {
+        Some(Follow::Descriptor)
+    } else {
+        None
+    };
+
+    let seconds = matches.value_of("sleep_interval").unwrap();
+    let sleep_interval = seconds
+        .parse::<f64>()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| format!("invalid number of seconds -- {}", seconds))?;
+
+    Ok(Config {
+        files: matches.values_of_lossy("files").unwrap(),
+        lines: lines.unwrap(),
+        bytes,
+        quiet: matches.is_present("quiet"),
+        follow,
+        sleep_interval,
+    })
+}
+
+fn parse_num(s: &str) -> MyResult<TakeValue> {
+    let (plus, digits) = match s.strip_prefix('+') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('-').unwrap_or(s)),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(From::from(s));
+    }
+    let num = digits.parse::<i64>().map_err(|_| s)?;
+    Ok(match (plus, num) {
+        (true, 0) => PlusZero,
+        (true, num) => TakeNum(num),
+        (false, num) => TakeNum(-num),
+    })
+}
+
+#[test]
+fn test_parse_num() {
+    // All integers should be interpreted as negative numbers
+    let res = parse_num("3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), TakeNum(-3));
+
+    // A leading "+" should result in a positive number
+    let res = parse_num("+3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), TakeNum(3));
+
+    // An explicit "-" value should result in a negative number
+    let res = parse_num("-3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), TakeNum(-3));
+
+    // Zero is zero
+    let res = parse_num("0");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), TakeNum(0));
+
+    // Plus zero is special
+    let res = parse_num("+0");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), PlusZero);
+
+    // Any noninteger string is an error
+    for bad in ["3.14", "foo", "", "+", "--3", "+-3", "3K"] {
+        let res = parse_num(bad);
+        assert!(res.is_err());
+        assert_eq!(res.unwrap_err().to_string(), bad);
+    }
+
+    // Values too large for an i64 are an error
+    let res = parse_num("99999999999999999999");
+    assert!(res.is_err());
+}
+
+pub fn run(config: Config) -> MyResult<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let show_headers = config.files.len() > 1 && !config.quiet;
+    let mut file_num = 0;
+    let mut followed = Vec::new();
+    // which followed file, if any, the output so far ended with
+    let mut last_shown = None;
+    for filename in &config.files {
+        match open(filename) {
+            Err(err) => {
+                eprintln!("Failed to open {}: {}", filename, err);
+                // -F keeps trying until the file shows up
+                if config.follow == Some(Follow::Name) {
+                    followed.push(Followed::missing(filename));
+                }
+            }
+            Ok(input) => {
+                if show_headers {
+                    writeln!(out, "{}==> {} <==",
+                    if file_num > 0 { "\n" } else { "" },
+                    filename)?;
+                    file_num += 1;
+                }
+                last_shown = None;
+                match input {
+                    Input::File(mut file) => {
+                        let pos = tail_file(&mut file, &config, &mut out)?;
+                        if config.follow.is_some() {
+                            last_shown = Some(followed.len());
+                            followed.push(Followed::new(filename, file, pos));
+                        }
+                    }
+                    Input::Stream(fh) => tail_stream(fh, &config, &mut out)?,
+                }
+            }
+        };
+    }
+    out.flush()?;
+
+    if !followed.is_empty() {
+        follow(&config, followed, last_shown, &mut out)?;
+    }
+    Ok(())
+}
+
+enum Input {
+    // a regular file, which we can seek around in
+    File(File),
+    Stream(Box<dyn BufRead>),
+}
+
+fn open(filename: &str) -> MyResult<Input> {
+    match filename {
+        "-" => Ok(Input::Stream(Box::new(BufReader::new(io::stdin())))),
+        _ => {
+            let file = File::open(filename)?;
+            if file.metadata()?.is_file() {
+                Ok(Input::File(file))
+            } else {
+                Ok(Input::Stream(Box::new(BufReader::new(file))))
+            }
+        }
+    }
+}
+
+// Print the requested tail of a regular file, seeking straight to it.
+// Returns the offset we stopped at, where following picks up.
+fn tail_file(
+    file: &mut File,
+    config: &Config,
+    out: &mut impl Write,
+) -> MyResult<u64> {
+    let len = file.metadata()?.len();
+    let start = match config.bytes {
+        Some(PlusZero) => 0,
+        Some(TakeNum(num)) if num > 0 => cmp::min(num as u64 - 1, len),
+        Some(TakeNum(num)) => len.saturating_sub(num.unsigned_abs()),
+        None => match config.lines {
+            PlusZero => 0,
+            TakeNum(0) => len,
+            TakeNum(num) if num > 0 => line_start_from_beginning(file, num as u64 - 1)?,
+            TakeNum(num) => line_start_from_end(file, len, num.unsigned_abs())?,
+        },
+    };
+    file.seek(SeekFrom::Start(start))?;
+    io::copy(file, out)?;
+    Ok(file.stream_position()?)
+}
+
+// The offset just past the first `skip` lines
+fn line_start_from_beginning(file: &mut File, skip: u64) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let mut pos = 0;
+    let mut line = Vec::new();
+    for _ in 0..skip {
+        line.clear();
+        let bytes = reader.read_until(b'\n', &mut line)?;
+        if bytes == 0 { break; }
+        pos += bytes as u64;
+    }
+    Ok(pos)
+}
+
+// The offset where the last `num` lines begin, found by reading blocks
+// backwards from the end of the file
+fn line_start_from_end(file: &mut File, len: u64, num: u64) -> io::Result<u64> {
+    let mut buf = vec![0; BLOCK_SIZE];
+    let mut newlines = 0;
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK_SIZE as u64);
+        let block = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(block)?;
+        for (i, &b) in block.iter().enumerate().rev() {
+            let offset = start + i as u64;
+            // the newline that ends the last line doesn't start a new one
+            if b == b'\n' && offset != len - 1 {
+                newlines += 1;
+                if newlines == num {
+                    return Ok(offset + 1);
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// Print the requested tail of something we can only read forward,
+// holding back no more than the last NUM lines or bytes
+fn tail_stream(
+    mut fh: impl BufRead,
+    config: &Config,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let take = config.bytes.unwrap_or(config.lines);
+    match take {
+        PlusZero => {
+            io::copy(&mut fh, out)?;
+        }
+        TakeNum(0) => {}
+        TakeNum(num) if num > 0 => {
+            let skip = num as u64 - 1;
+            if config.bytes.is_some() {
+                io::copy(&mut (&mut fh).take(skip), &mut io::sink())?;
+            } else {
+                let mut line = Vec::new();
+                for _ in 0..skip {
+                    line.clear();
+                    if fh.read_until(b'\n', &mut line)? == 0 { break; }
+                }
+            }
+            io::copy(&mut fh, out)?;
+        }
+        TakeNum(num) if config.bytes.is_some() => {
+            let num = num.unsigned_abs() as usize;
+            let mut held: VecDeque<u8> = VecDeque::new();
+            loop {
+                let buf = fh.fill_buf()?;
+                if buf.is_empty() { break; }
+                let len = buf.len();
+                held.extend(buf);
+                fh.consume(len);
+                if held.len() > num {
+                    held.drain(..held.len() - num);
+                }
+            }
+            let (front, back) = held.as_slices();
+            out.write_all(front)?;
+            out.write_all(back)?;
+        }
+        TakeNum(num) => {
+            let num = num.unsigned_abs() as usize;
+            let mut held: VecDeque<Vec<u8>> = VecDeque::new();
+            loop {
+                let mut line = Vec::new();
+                if fh.read_until(b'\n', &mut line)? == 0 { break; }
+                if held.len() == num {
+                    held.pop_front();
+                }
+                held.push_back(line);
+            }
+            for line in held {
+                out.write_all(&line)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A file being followed with -f or -F
+struct Followed {
+    name: String,
+    file: Option<File>,
+    // identity of the open file, to notice when the name is reused
+    id: Option<(u64, u64)>,
+    pos: u64,
+    missing: bool,
+}
+
+impl Followed {
+    fn new(name: &str, file: File, pos: u64) -> Followed {
+        Followed {
+            name: name.to_string(),
+            id: file.metadata().ok().map(|meta| file_id(&meta)),
+            file: Some(file),
+            pos,
+            missing: false,
+        }
+    }
+
+    fn missing(name: &str) -> Followed {
+        Followed {
+            name: name.to_string(),
+            file: None,
+            id: None,
+            pos: 0,
+            missing: true,
+        }
+    }
+
+    // Start over from the top if the file has shrunk
+    fn check_truncated(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            if file.metadata()?.len() < self.pos {
+                eprintln!("{}: file truncated", self.name);
+                file.seek(SeekFrom::Start(0))?;
+                self.pos = 0;
+            }
+        }
+        Ok(())
+    }
+
+    // With -F, switch to a different file that has appeared under our
+    // name, as happens when a log is rotated. Returns true if we did.
+    fn reopen_if_replaced(&mut self) -> bool {
+        let id = match fs::metadata(&self.name) {
+            Ok(meta) => file_id(&meta),
+            Err(err) => {
+                if !self.missing {
+                    eprintln!("{}: has become inaccessible: {}", self.name, err);
+                    self.missing = true;
+                }
+                return false;
+            }
+        };
+        self.missing = false;
+        if self.file.is_some() && self.id == Some(id) {
+            return false;
+        }
+        match File::open(&self.name) {
+            Err(_) => false,
+            Ok(file) => {
+                if self.file.is_some() {
+                    eprintln!("{}: file has been replaced; following new file", self.name);
+                } else {
+                    eprintln!("{}: file has appeared; following new file", self.name);
+                }
+                self.id = file.metadata().ok().map(|meta| file_id(&meta));
+                self.file = Some(file);
+                self.pos = 0;
+                true
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+// Print data as it is appended to the followed files, forever
+fn follow(
+    config: &Config,
+    mut files: Vec<Followed>,
+    mut last_shown: Option<usize>,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let show_headers = config.files.len() > 1 && !config.quiet;
+    let mut buf = vec![0; BLOCK_SIZE];
+    let mut waiter = Waiter::new(&files, config.sleep_interval);
+    loop {
+        for (i, followed) in files.iter_mut().enumerate() {
+            followed.check_truncated()?;
+            // drain the old file before moving on to its replacement
+            loop {
+                if let Some(file) = &mut followed.file {
+                    loop {
+                        let bytes = file.read(&mut buf)?;
+                        if bytes == 0 { break; }
+                        if show_headers && last_shown != Some(i) {
+                            writeln!(out, "\n==> {} <==", followed.name)?;
+                            last_shown = Some(i);
+                        }
+                        out.write_all(&buf[..bytes])?;
+                        followed.pos += bytes as u64;
+                    }
+                }
+                if config.follow != Some(Follow::Name)
+                    || !followed.reopen_if_replaced()
+                {
+                    break;
+                }
+            }
+        }
+        out.flush()?;
+        waiter.wait()?;
+    }
+}
+
+// Sleeps until a followed file may have changed. On Linux, inotify
+// watches on the files' directories wake us up early; without inotify
+// we fall back to checking every sleep interval.
+struct Waiter {
+    interval: Duration,
+    #[cfg(target_os = "linux")]
+    inotify: Option<inotify::Inotify>,
+}
+
+impl Waiter {
+    fn new(files: &[Followed], interval: Duration) -> Waiter {
+        #[cfg(target_os = "linux")]
+        {
+            Waiter {
+                interval,
+                inotify: watch_dirs(files).ok(),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = files;
+            Waiter { interval }
+        }
+    }
+
+    fn wait(&mut self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = &mut self.inotify {
+            return wait_inotify(inotify, self.interval);
+        }
+        thread::sleep(self.interval);
+        Ok(())
+    }
+}
+
+// Watching the directories rather than the files themselves means the
+// watches survive the files being rotated, removed and recreated
+#[cfg(target_os = "linux")]
+fn watch_dirs(files: &[Followed]) -> io::Result<inotify::Inotify> {
+    use inotify::{Inotify, WatchMask};
+    use std::path::Path;
+
+    let inotify = Inotify::init()?;
+    for followed in files {
+        let dir = match Path::new(&followed.name).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        inotify.watches().add(
+            dir,
+            WatchMask::MODIFY
+                | WatchMask::ATTRIB
+                | WatchMask::CREATE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO,
+        )?;
+    }
+    Ok(inotify)
+}
+
+// Block until there are inotify events or the interval runs out, so
+// that changes inotify can't see are still picked up eventually
+#[cfg(target_os = "linux")]
+fn wait_inotify(inotify: &mut inotify::Inotify, interval: Duration) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fds = libc::pollfd {
+        fd: inotify.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = cmp::min(interval.as_millis(), i32::MAX as u128) as i32;
+    if unsafe { libc::poll(&mut fds, 1, timeout) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    let mut buffer = [0; 4096];
+    loop {
+        match inotify.read_events(&mut buffer) {
+            Ok(events) => {
+                if events.count() == 0 { break; }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
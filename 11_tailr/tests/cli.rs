@@ -0,0 +1,298 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use rand::{distributions::Alphanumeric, Rng};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+type TestResult = Result<(), Box<dyn Error>>;
+
+const PRG: &str = "tailr";
+const EMPTY: &str = "tests/inputs/empty.txt";
+const ONE: &str = "tests/inputs/one.txt";
+const TWO: &str = "tests/inputs/two.txt";
+const THREE: &str = "tests/inputs/three.txt";
+const TEN: &str = "tests/inputs/ten.txt";
+
+// --------------------------------------------------
+fn random_string() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(7)
+        .map(char::from)
+        .collect()
+}
+
+// --------------------------------------------------
+fn gen_bad_file() -> String {
+    loop {
+        let filename = random_string();
+        if fs::metadata(&filename).is_err() {
+            return filename;
+        }
+    }
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_bytes() -> TestResult {
+    let bad = random_string();
+    let expected = format!("illegal byte count -- {}", &bad);
+    Command::cargo_bin(PRG)?
+        .args(["-c", &bad, EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(expected));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_lines() -> TestResult {
+    let bad = random_string();
+    let expected = format!("illegal line count -- {}", &bad);
+    Command::cargo_bin(PRG)?
+        .args(["-n", &bad, EMPTY])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(expected));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bytes_and_lines() -> TestResult {
+    let msg = "The argument '--lines <LINES>' cannot be \
+               used with '--bytes <BYTES>'";
+
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", "-c", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(msg));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skips_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("{}: .* [(]os error 2[)]", bad);
+    Command::cargo_bin(PRG)?
+        .args([ONE, &bad, TWO])
+        .assert()
+        .stderr(predicate::str::is_match(expected)?);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+fn run(args: &[&str], expected_file: &str) -> TestResult {
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .args(args)
+        .assert()
+        .success()
+        .stdout(predicate::eq(expected.as_slice()));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+fn run_stdin(
+    args: &[&str],
+    input_file: &str,
+    expected_file: &str,
+) -> TestResult {
+    let input = fs::read(input_file)?;
+    let expected = fs::read(expected_file)?;
+    Command::cargo_bin(PRG)?
+        .write_stdin(input)
+        .args(args)
+        .assert()
+        .success()
+        .stdout(predicate::eq(expected.as_slice()));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+// Check a file both directly and through STDIN
+fn run_both(args: &[&str], input_file: &str, expected_file: &str) -> TestResult {
+    let mut file_args = args.to_vec();
+    file_args.push(input_file);
+    run(&file_args, expected_file)?;
+    run_stdin(args, input_file, expected_file)
+}
+
+// --------------------------------------------------
+fn expected(input_file: &str, suffix: &str) -> String {
+    format!("{}{}.out", input_file.replace("inputs", "expected"), suffix)
+}
+
+// --------------------------------------------------
+#[test]
+fn default_lines() -> TestResult {
+    for file in &[EMPTY, ONE, TWO, THREE, TEN] {
+        run_both(&[], file, &expected(file, ""))?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn lines() -> TestResult {
+    for file in &[EMPTY, ONE, TWO, THREE, TEN] {
+        for num in &["0", "3", "+0", "+3"] {
+            run_both(&["-n", num], file, &expected(file, &format!(".n{}", num)))?;
+        }
+        run_both(&["-n", "-3"], file, &expected(file, ".n3"))?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes() -> TestResult {
+    for file in &[EMPTY, ONE, TWO, THREE, TEN] {
+        for num in &["0", "3", "+0", "+3"] {
+            run_both(&["-c", num], file, &expected(file, &format!(".c{}", num)))?;
+        }
+        run_both(&["-c", "-3"], file, &expected(file, ".c3"))?;
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files() -> TestResult {
+    run(
+        &["-n", "3", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.n3.out",
+    )?;
+    run(
+        &[EMPTY, ONE, TWO, THREE, TEN, "-q", "-c", "+3"],
+        "tests/expected/all.q.c+3.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn seeks_in_large_file() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("big.txt");
+    let mut file = File::create(&path)?;
+    for i in 1..=100_000 {
+        writeln!(file, "line {}", i)?;
+    }
+    let expected: String = (70_001..=100_000).map(|i| format!("line {}\n", i)).collect();
+    Command::cargo_bin(PRG)?
+        .args(["-n", "30000", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+// Start following `args`, let `act` change the files, then stop tailr
+// and return everything it printed
+fn follow(args: &[&str], act: impl FnOnce() -> TestResult) -> Result<String, Box<dyn Error>> {
+    let mut child = process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    thread::sleep(Duration::from_millis(500));
+    let result = act();
+    thread::sleep(Duration::from_millis(500));
+    child.kill()?;
+    let mut output = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut output)?;
+    child.wait()?;
+    result?;
+    Ok(output)
+}
+
+// --------------------------------------------------
+fn append(path: &std::path::Path, text: &str) -> TestResult {
+    OpenOptions::new().append(true).open(path)?.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_appends_and_truncation() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("app.log");
+    fs::write(&path, "one\ntwo\n")?;
+    let output = follow(&["-f", "-n", "1", "-s", "0.1", path.to_str().unwrap()], || {
+        append(&path, "three\n")?;
+        thread::sleep(Duration::from_millis(300));
+        // truncate and start again
+        fs::write(&path, "four\n")?;
+        Ok(())
+    })?;
+    assert_eq!(output, "two\nthree\nfour\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_name_across_rotation() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("app.log");
+    fs::write(&path, "old\n")?;
+    let output = follow(&["-F", "-s", "0.1", path.to_str().unwrap()], || {
+        append(&path, "last old\n")?;
+        thread::sleep(Duration::from_millis(300));
+        fs::rename(&path, dir.path().join("app.log.1"))?;
+        fs::write(&path, "new\n")?;
+        thread::sleep(Duration::from_millis(300));
+        append(&path, "newer\n")?;
+        Ok(())
+    })?;
+    assert_eq!(output, "old\nlast old\nnew\nnewer\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_name_waits_for_missing_file() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("later.log");
+    let output = follow(&["-F", "-s", "0.1", path.to_str().unwrap()], || {
+        fs::write(&path, "here now\n")?;
+        Ok(())
+    })?;
+    assert_eq!(output, "here now\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_header_after_stdin() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("fa.log");
+    fs::write(&path, "a\n")?;
+    let name = path.to_str().unwrap();
+    let output = follow(&["-f", "-s", "0.1", name, "-"], || append(&path, "b\n"))?;
+    assert_eq!(
+        output,
+        format!("==> {0} <==\na\n\n==> - <==\n\n==> {0} <==\nb\n", name)
+    );
+
+    Ok(())
+}